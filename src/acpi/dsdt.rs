@@ -0,0 +1,48 @@
+use super::sdt::{Sdt, HEADER_SIZE};
+
+pub const SIGNATURE: &[u8; 4] = b"DSDT";
+
+// aml opcodes the sleep state packages are made of
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const BYTE_PREFIX: u8 = 0x0a;
+const ROOT_CHAR: u8 = b'\\';
+
+// the SLP_TYPa and SLP_TYPb values of sleep state name, like b"_S5_"
+//
+// there's no aml interpreter, so this only finds the plain package firmware
+// declares them as: Name (_S5_, Package (n) { a, b, ... })
+pub fn sleep_type(dsdt: &Sdt, name: &[u8; 4]) -> Option<(u8, u8)> {
+    let bytes = dsdt.bytes();
+
+    let at = (HEADER_SIZE + 2..bytes.len().saturating_sub(4))
+        .find(|&idx| &bytes[idx..idx + 4] == name)?;
+    let named = bytes[at - 1] == NAME_OP
+        || (bytes[at - 1] == ROOT_CHAR && bytes[at - 2] == NAME_OP);
+    if !named {
+        return None;
+    }
+
+    let mut idx = at + 4;
+    if *bytes.get(idx)? != PACKAGE_OP {
+        return None;
+    }
+    idx += 1;
+
+    // the package length, its top two bits count the bytes that follow
+    let extra = (*bytes.get(idx)? >> 6) as usize;
+    // and the element count
+    idx += 1 + extra + 1;
+
+    let mut value = || {
+        if *bytes.get(idx)? == BYTE_PREFIX {
+            idx += 1;
+        }
+        let value = *bytes.get(idx)?;
+        idx += 1;
+        Some(value)
+    };
+    let a = value()?;
+    let b = value()?;
+    Some((a, b))
+}
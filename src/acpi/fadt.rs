@@ -0,0 +1,87 @@
+use arch::paging::addr::{PhysAddr, Physical};
+
+use super::sdt::{read, GenericAddress, Sdt};
+
+pub const SIGNATURE: &[u8; 4] = b"FACP";
+
+// iapc_boot_arch bits
+pub const BOOT_ARCH_LEGACY_DEVICES: u16 = 1 << 0;
+pub const BOOT_ARCH_8042: u16 = 1 << 1;
+
+// flags bits
+pub const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+// the fixed acpi description table, mostly the power management ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fadt {
+    // X_DSDT if it's set, DSDT otherwise
+    pub dsdt: Physical,
+    pub sci_interrupt: u16,
+    // writing acpi_enable here switches from legacy to acpi mode, 0 if
+    // there's no legacy mode
+    pub smi_command: u16,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event: u16,
+    pub pm1b_event: u16,
+    pub pm1a_control: u16,
+    pub pm1b_control: u16,
+    pub pm_timer: u16,
+    pub pm1_event_len: u8,
+    pub pm1_control_len: u8,
+    pub pm_timer_len: u8,
+    pub century: u8,
+    pub boot_arch: u16,
+    pub flags: u32,
+    // the reset register and the value that resets the machine, acpi 2.0
+    pub reset: Option<(GenericAddress, u8)>,
+}
+
+impl Fadt {
+    // acpi 1.0 tables end before the reset register
+    pub fn parse(sdt: &Sdt) -> Option<Fadt> {
+        let bytes = sdt.bytes();
+        let port = |offset| read::<u32>(bytes, offset).map(|port| port as u16);
+
+        let reset = read::<GenericAddress>(bytes, 116)
+            .and_then(|reg| read::<u8>(bytes, 128).map(|value| (reg, value)));
+
+        // acpi 2.0's 64-bit pointer, only usable if it fits a Physical
+        let x_dsdt = read::<u64>(bytes, 140).and_then(|addr| {
+            if addr != 0 && addr <= PhysAddr::max_value() as u64 {
+                Some(addr as PhysAddr)
+            } else {
+                None
+            }
+        });
+        let dsdt = match x_dsdt {
+            Some(addr) => addr,
+            None => read::<u32>(bytes, 40)? as PhysAddr,
+        };
+
+        Some(Fadt {
+            dsdt: Physical::new(dsdt),
+            sci_interrupt: read(bytes, 46)?,
+            smi_command: port(48)?,
+            acpi_enable: read(bytes, 52)?,
+            acpi_disable: read(bytes, 53)?,
+            pm1a_event: port(56)?,
+            pm1b_event: port(60)?,
+            pm1a_control: port(64)?,
+            pm1b_control: port(68)?,
+            pm_timer: port(76)?,
+            pm1_event_len: read(bytes, 88)?,
+            pm1_control_len: read(bytes, 89)?,
+            pm_timer_len: read(bytes, 91)?,
+            century: read(bytes, 108)?,
+            boot_arch: read(bytes, 109)?,
+            flags: read(bytes, 112)?,
+            reset,
+        })
+    }
+
+    // whether reset is there and meant to be used
+    pub fn can_reset(&self) -> bool {
+        self.flags & FLAG_RESET_REG_SUP != 0 && self.reset.is_some()
+    }
+}
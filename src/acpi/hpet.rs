@@ -0,0 +1,38 @@
+use arch::paging::addr::{PhysAddr, Physical};
+
+use super::sdt::{read, GenericAddress, Sdt, HEADER_SIZE};
+
+pub const SIGNATURE: &[u8; 4] = b"HPET";
+
+// the high precision event timer description table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hpet {
+    // hardware revision, comparator count, counter size and vendor
+    pub block_id: u32,
+    // the registers, always in memory
+    pub base: Physical,
+    pub number: u8,
+    // the smallest tick the periodic mode can do without losing interrupts
+    pub min_tick: u16,
+}
+
+impl Hpet {
+    pub fn parse(sdt: &Sdt) -> Option<Hpet> {
+        let bytes = sdt.bytes();
+        let base = read::<GenericAddress>(bytes, HEADER_SIZE + 4)?.address;
+        if base > PhysAddr::max_value() as u64 {
+            return None;
+        }
+
+        Some(Hpet {
+            block_id: read(bytes, HEADER_SIZE)?,
+            base: Physical::new(base as PhysAddr),
+            number: read(bytes, HEADER_SIZE + 16)?,
+            min_tick: read(bytes, HEADER_SIZE + 17)?,
+        })
+    }
+
+    pub fn comparators(&self) -> u8 {
+        ((self.block_id >> 8) & 0x1f) as u8 + 1
+    }
+}
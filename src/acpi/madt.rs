@@ -0,0 +1,143 @@
+use alloc::vec::Vec;
+
+use arch::paging::addr::{PhysAddr, Physical};
+
+use super::sdt::{read, Sdt, HEADER_SIZE};
+
+pub const SIGNATURE: &[u8; 4] = b"APIC";
+
+// the entry types the kernel cares about
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const SOURCE_OVERRIDE: u8 = 2;
+const LOCAL_NMI: u8 = 4;
+const LOCAL_ADDRESS: u8 = 5;
+
+// the system has 8259s, which have to be disabled when the apic is used
+const PCAT_COMPAT: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cpu {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    // disabled cpus can't be started
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IoApic {
+    pub id: u8,
+    pub addr: Physical,
+    // the first global system interrupt it handles
+    pub gsi_base: u32,
+}
+
+// an isa irq that isn't wired to the gsi with its number, or not edge
+// triggered and active high
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Override {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl Override {
+    // the polarity bits, 0b11 is active low, anything else means isa default
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    // the trigger mode bits, 0b11 is level, anything else means isa default
+    pub fn level(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+// a local apic input that's wired to nmi
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Nmi {
+    // 0xff means every cpu
+    pub processor_id: u8,
+    pub flags: u16,
+    pub lint: u8,
+}
+
+// the multiple apic description table
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Madt {
+    pub local_apic: Physical,
+    pub flags: u32,
+    pub cpus: Vec<Cpu>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<Override>,
+    pub nmis: Vec<Nmi>,
+}
+
+impl Madt {
+    pub fn parse(sdt: &Sdt) -> Option<Madt> {
+        let bytes = sdt.bytes();
+        let local_apic = read::<u32>(bytes, HEADER_SIZE)? as PhysAddr;
+        let mut madt = Madt {
+            local_apic: Physical::new(local_apic),
+            flags: read::<u32>(bytes, HEADER_SIZE + 4)?,
+            cpus: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        let mut offset = HEADER_SIZE + 8;
+        while let (Some(typ), Some(len)) =
+            (read::<u8>(bytes, offset), read::<u8>(bytes, offset + 1))
+        {
+            let len = len as usize;
+            if len < 2 || offset + len > bytes.len() {
+                break;
+            }
+            let entry = &bytes[offset..offset + len];
+
+            match typ {
+                LOCAL_APIC => madt.cpus.push(Cpu {
+                    processor_id: read(entry, 2)?,
+                    apic_id: read(entry, 3)?,
+                    enabled: read::<u32>(entry, 4)? & 1 != 0,
+                }),
+                IO_APIC => madt.io_apics.push(IoApic {
+                    id: read(entry, 2)?,
+                    addr: Physical::new(read::<u32>(entry, 4)? as PhysAddr),
+                    gsi_base: read(entry, 8)?,
+                }),
+                SOURCE_OVERRIDE => madt.overrides.push(Override {
+                    source: read(entry, 3)?,
+                    gsi: read(entry, 4)?,
+                    flags: read(entry, 8)?,
+                }),
+                LOCAL_NMI => madt.nmis.push(Nmi {
+                    processor_id: read(entry, 2)?,
+                    flags: read(entry, 3)?,
+                    lint: read(entry, 5)?,
+                }),
+                LOCAL_ADDRESS => {
+                    let addr = read::<u64>(entry, 4)?;
+                    if addr <= PhysAddr::max_value() as u64 {
+                        madt.local_apic = Physical::new(addr as PhysAddr);
+                    }
+                }
+                _ => {}
+            }
+
+            offset += len;
+        }
+
+        Some(madt)
+    }
+
+    pub fn has_pics(&self) -> bool {
+        self.flags & PCAT_COMPAT != 0
+    }
+
+    // the override for isa irq source, if there is one
+    pub fn source_override(&self, source: u8) -> Option<&Override> {
+        self.overrides.iter().find(|over| over.source == source)
+    }
+}
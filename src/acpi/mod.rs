@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+
+use arch::paging::addr::{PhysAddr, Physical};
+
+pub mod sdt;
+pub mod rsdp;
+pub mod madt;
+pub mod fadt;
+pub mod hpet;
+pub mod dsdt;
+
+pub use self::sdt::{GenericAddress, Sdt, SdtHeader};
+pub use self::rsdp::Rsdp;
+pub use self::madt::Madt;
+pub use self::fadt::Fadt;
+pub use self::hpet::Hpet;
+
+const RSDT: &[u8; 4] = b"RSDT";
+const XSDT: &[u8; 4] = b"XSDT";
+
+// what the kernel learned from the firmware tables, the tables themselves
+// aren't kept mapped
+#[derive(Debug)]
+pub struct Acpi {
+    pub rsdp: Rsdp,
+    // the signature and address of every valid table the root table lists
+    pub tables: Vec<([u8; 4], Physical)>,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+}
+
+impl Acpi {
+    // where the table with signature is, for tables without a parser
+    pub fn find(&self, signature: &[u8; 4]) -> Option<Physical> {
+        self.tables
+            .iter()
+            .find(|&&(sig, _)| &sig == signature)
+            .map(|&(_, phys)| phys)
+    }
+}
+
+// finds and parses the tables, rsdp is the copy multiboot passed if there was
+// one, otherwise the bios areas are scanned
+//
+// None if there's no acpi or the root table is broken
+pub fn init(rsdp: Option<Rsdp>) -> Option<Acpi> {
+    let rsdp = rsdp.or_else(Rsdp::scan)?;
+
+    let mut acpi = Acpi {
+        rsdp,
+        tables: Vec::new(),
+        madt: None,
+        fadt: None,
+        hpet: None,
+    };
+
+    for phys in root_entries(&rsdp)? {
+        let sdt = match Sdt::map(phys) {
+            Some(sdt) => sdt,
+            None => continue,
+        };
+        let signature = sdt.signature();
+        acpi.tables.push((signature, phys));
+
+        // the first valid one of each wins
+        if &signature == madt::SIGNATURE && acpi.madt.is_none() {
+            acpi.madt = Madt::parse(&sdt);
+        } else if &signature == fadt::SIGNATURE && acpi.fadt.is_none() {
+            acpi.fadt = Fadt::parse(&sdt);
+        } else if &signature == hpet::SIGNATURE && acpi.hpet.is_none() {
+            acpi.hpet = Hpet::parse(&sdt);
+        }
+    }
+
+    Some(acpi)
+}
+
+// the tables the xsdt lists, or the rsdt without one
+fn root_entries(rsdp: &Rsdp) -> Option<Vec<Physical>> {
+    if let Some(xsdt) = rsdp.xsdt.and_then(Sdt::map) {
+        if &xsdt.signature() == XSDT {
+            let data = xsdt.data();
+            let entries = (0..data.len() / 8)
+                .filter_map(|idx| sdt::read::<u64>(data, idx * 8))
+                .filter(|&addr| addr <= PhysAddr::max_value() as u64)
+                .map(|addr| Physical::new(addr as PhysAddr))
+                .collect();
+            return Some(entries);
+        }
+    }
+
+    let rsdt = Sdt::map(rsdp.rsdt)?;
+    if &rsdt.signature() != RSDT {
+        return None;
+    }
+    let data = rsdt.data();
+    let entries = (0..data.len() / 4)
+        .filter_map(|idx| sdt::read::<u32>(data, idx * 4))
+        .map(|addr| Physical::new(addr as PhysAddr))
+        .collect();
+    Some(entries)
+}
@@ -0,0 +1,134 @@
+use core::slice;
+
+use arch::paging::addr::*;
+use mem::mmio;
+
+use super::sdt::{checksum, read};
+
+const SIGNATURE: &[u8] = b"RSD PTR ";
+
+// the acpi 1.0 part, revision 2 appends the length, the xsdt and a checksum
+// over all of it
+const V1_SIZE: usize = 20;
+const V2_SIZE: usize = 36;
+
+// multiboot2 passes copies of the rsdp in these tags
+const TAG_END: u32 = 0;
+const TAG_RSDP_V1: u32 = 14;
+const TAG_RSDP_V2: u32 = 15;
+
+// the segment of the extended bios data area is stored at 0x40e, the rsdp is
+// in its first KiB or somewhere in the bios area
+const EBDA_SEGMENT: PhysAddr = 0x40e;
+const EBDA_SCAN: usize = 1024;
+const BIOS_START: usize = 0xe0000;
+const BIOS_END: usize = 0x100000;
+
+// the root system description pointer, it leads to the rsdt and on newer
+// firmware to the xsdt with 64-bit entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub rsdt: Physical,
+    // None before revision 2 or when it's out of reach
+    pub xsdt: Option<Physical>,
+}
+
+impl Rsdp {
+    // None unless bytes start with a valid rsdp
+    pub fn parse(bytes: &[u8]) -> Option<Rsdp> {
+        if bytes.len() < V1_SIZE
+            || &bytes[..SIGNATURE.len()] != SIGNATURE
+            || !checksum(&bytes[..V1_SIZE])
+        {
+            return None;
+        }
+
+        let revision = read::<u8>(bytes, 15)?;
+        let oem_id = read::<[u8; 6]>(bytes, 9)?;
+        let rsdt = Physical::new(read::<u32>(bytes, 16)? as PhysAddr);
+
+        let mut xsdt = None;
+        if revision >= 2 && bytes.len() >= V2_SIZE {
+            let len = read::<u32>(bytes, 20)? as usize;
+            let xsdt_addr = read::<u64>(bytes, 24)?;
+            if len >= V2_SIZE && len <= bytes.len() && checksum(&bytes[..len])
+                && xsdt_addr <= PhysAddr::max_value() as u64
+            {
+                xsdt = Some(Physical::new(xsdt_addr as PhysAddr));
+            }
+        }
+
+        Some(Rsdp {
+            revision,
+            oem_id,
+            rsdt,
+            xsdt,
+        })
+    }
+
+    // the copy multiboot2 made, mb2 is the address of the boot information,
+    // which must still be mapped
+    //
+    // the acpi 2.0 copy wins over the 1.0 one
+    pub unsafe fn from_multiboot(mb2: usize) -> Option<Rsdp> {
+        let total = *(mb2 as *const u32) as usize;
+        let end = mb2 + total;
+
+        let mut found = None;
+        let mut tag = mb2 + 8;
+        while tag + 8 <= end {
+            let typ = *(tag as *const u32);
+            let size = *((tag + 4) as *const u32) as usize;
+            if typ == TAG_END || size < 8 {
+                break;
+            }
+
+            let data = slice::from_raw_parts((tag + 8) as *const u8, size - 8);
+            match typ {
+                TAG_RSDP_V2 => {
+                    if let Some(rsdp) = Rsdp::parse(data) {
+                        return Some(rsdp);
+                    }
+                }
+                TAG_RSDP_V1 => found = found.or_else(|| Rsdp::parse(data)),
+                _ => {}
+            }
+
+            // tags are 8-byte aligned
+            tag += (size + 7) & !7;
+        }
+        found
+    }
+
+    // scans the places bios firmware puts the rsdp at, every 16 bytes
+    pub fn scan() -> Option<Rsdp> {
+        let ebda = {
+            let addr = mmio::map(Physical::new(EBDA_SEGMENT), 2)?;
+            let segment = unsafe { *(addr.into_inner() as *const u16) };
+            mmio::unmap(addr, 2);
+            (segment as usize) << 4
+        };
+
+        if ebda != 0 {
+            if let Some(rsdp) = scan_area(ebda, EBDA_SCAN) {
+                return Some(rsdp);
+            }
+        }
+        scan_area(BIOS_START, BIOS_END - BIOS_START)
+    }
+}
+
+fn scan_area(start: usize, len: usize) -> Option<Rsdp> {
+    let addr = mmio::map(Physical::new(start as PhysAddr), len)?;
+    let area =
+        unsafe { slice::from_raw_parts(addr.into_inner() as *const u8, len) };
+
+    let rsdp = (0..len / 16)
+        .filter_map(|idx| Rsdp::parse(&area[idx * 16..]))
+        .next();
+
+    mmio::unmap(addr, len);
+    rsdp
+}
@@ -0,0 +1,109 @@
+use core::mem;
+use core::ptr;
+use core::slice;
+
+use arch::paging::addr::*;
+use mem::mmio;
+
+// the header every system description table starts with
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+pub const HEADER_SIZE: usize = 36;
+
+// where a register lives, ports or memory, in the fadt and hpet tables
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GenericAddress {
+    pub space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const SPACE_MEMORY: u8 = 0;
+pub const SPACE_IO: u8 = 1;
+
+// the bytes of every acpi structure add up to 0
+pub fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+// a T at offset, None if it doesn't fit into bytes, which is how fields newer
+// revisions appended are left out
+pub fn read<T: Copy>(bytes: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(mem::size_of::<T>())?;
+    if end > bytes.len() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(bytes[offset..].as_ptr() as *const T) })
+}
+
+// a table mapped into the kernel half, it's unmapped when dropped
+#[derive(Debug)]
+pub struct Sdt {
+    addr: Virtual,
+    len: usize,
+}
+
+impl Sdt {
+    // None if the table can't be mapped or its checksum is wrong
+    pub fn map(phys: Physical) -> Option<Sdt> {
+        let header = Sdt {
+            addr: mmio::map(phys, HEADER_SIZE)?,
+            len: HEADER_SIZE,
+        };
+        let len = header.header().length as usize;
+        drop(header);
+
+        if len < HEADER_SIZE {
+            return None;
+        }
+        let sdt = Sdt {
+            addr: mmio::map(phys, len)?,
+            len,
+        };
+        if checksum(sdt.bytes()) {
+            Some(sdt)
+        } else {
+            None
+        }
+    }
+
+    pub fn header(&self) -> SdtHeader {
+        read(self.bytes(), 0).unwrap()
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        self.header().signature
+    }
+
+    // the whole table, header included, so offsets match the spec
+    pub fn bytes(&self) -> &[u8] {
+        let addr = self.addr.into_inner() as *const u8;
+        unsafe { slice::from_raw_parts(addr, self.len) }
+    }
+
+    // what follows the header
+    pub fn data(&self) -> &[u8] {
+        &self.bytes()[HEADER_SIZE..]
+    }
+}
+
+impl Drop for Sdt {
+    fn drop(&mut self) {
+        mmio::unmap(self.addr, self.len);
+    }
+}
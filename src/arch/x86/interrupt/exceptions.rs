@@ -1,105 +1,457 @@
-use arch::interrupt::ExceptionStackFrame;
+use core::fmt;
+use core::mem;
+use core::ptr;
 
-pub unsafe extern "x86-interrupt" fn de(_stack_frame: &ExceptionStackFrame) {
-    panic!("divide-by-zero error"); // 0x0
-}
+use spin::Once;
 
-pub unsafe extern "x86-interrupt" fn db(_stack_frame: &ExceptionStackFrame) {
-    panic!("debug"); // 0x1
-}
+use x86::shared::control_regs::{cr0, cr2, cr3, cr4};
 
-pub unsafe extern "x86-interrupt" fn ni(_stack_frame: &ExceptionStackFrame) {
-    panic!("non-maskable interrupt"); // 0x2
-}
+use arch::kernel;
+use arch::paging::addr::Virtual;
+use mem::lazy;
+use mem::space;
+use mem::stack;
 
-pub unsafe extern "x86-interrupt" fn bp(_stack_frame: &ExceptionStackFrame) {
-    panic!("breakpoint"); // 0x3
+bitflags! {
+    pub struct PageFaultError: usize {
+        const PRESENT = 0b00001; // protection violation, not a missing page
+        const WRITE = 0b00010;
+        const USER = 0b00100;
+        const RESERVED = 0b01000;
+        const INSTRUCTION = 0b10000;
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn of(_stack_frame: &ExceptionStackFrame) {
-    panic!("overflow"); // 0x4
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cause = if self.contains(PageFaultError::PRESENT) {
+            "protection violation"
+        } else {
+            "page not present"
+        };
+        let access = if self.contains(PageFaultError::INSTRUCTION) {
+            "instruction fetch"
+        } else if self.contains(PageFaultError::WRITE) {
+            "write"
+        } else {
+            "read"
+        };
+        let mode = if self.contains(PageFaultError::USER) {
+            "user"
+        } else {
+            "kernel"
+        };
+
+        write!(f, "{} on {} in {} mode", cause, access, mode)?;
+        if self.contains(PageFaultError::RESERVED) {
+            write!(f, ", reserved bit set")?;
+        }
+        Ok(())
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn br(_stack_frame: &ExceptionStackFrame) {
-    panic!("bound range exceeded"); // 0x5
+// the error code of #TS, #NP, #SS and #GP, it names the selector that caused
+// the fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SelectorError(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
 }
 
-pub unsafe extern "x86-interrupt" fn ud(_stack_frame: &ExceptionStackFrame) {
-    panic!("invalid opcode"); // 0x6
+impl SelectorError {
+    pub fn new(code: usize) -> SelectorError {
+        SelectorError(code & 0xffff)
+    }
+
+    // the fault happened while delivering an interrupt from outside
+    pub fn external(&self) -> bool {
+        self.0 & 0b001 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        if self.0 & 0b010 != 0 {
+            DescriptorTable::Idt
+        } else if self.0 & 0b100 != 0 {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 >> 3
+    }
+
+    // the selector as it would be loaded, without the requested privilege
+    // level
+    pub fn selector(&self) -> u16 {
+        (self.0 & !0b011) as u16
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn nm(_stack_frame: &ExceptionStackFrame) {
-    panic!("device not available"); // 0x7
+impl fmt::Display for DescriptorTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DescriptorTable::Gdt => write!(f, "gdt"),
+            DescriptorTable::Idt => write!(f, "idt"),
+            DescriptorTable::Ldt => write!(f, "ldt"),
+        }
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn df(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("double fault"); // 0x8
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "no selector");
+        }
+        match self.table() {
+            DescriptorTable::Idt => {
+                write!(f, "idt vector {:02x}", self.index())?
+            }
+            table => write!(f, "{} selector {:04x}", table, self.selector())?,
+        }
+        if self.external() {
+            write!(f, ", external event")?;
+        }
+        Ok(())
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn ts(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("invalid tss"); // 0xA
+// the state the entry stubs save, in the order they push it
+//
+// user_esp and user_ss are only there when the exception came from user mode,
+// they must not be read otherwise
+#[repr(C)]
+pub struct Registers {
+    pub edi: usize,
+    pub esi: usize,
+    pub ebp: usize,
+    // where pushal found esp, it points at vector
+    pub kernel_esp: usize,
+    pub ebx: usize,
+    pub edx: usize,
+    pub ecx: usize,
+    pub eax: usize,
+    pub vector: usize,
+    // 0 for exceptions without an error code
+    pub code: usize,
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+    pub user_esp: usize,
+    pub user_ss: usize,
 }
 
-pub unsafe extern "x86-interrupt" fn np(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("segment not present"); // 0xB
+// vector, code, eip, cs and eflags lie between kernel_esp and the stack
+// pointer of the interrupted kernel code
+const KERNEL_FRAME: usize = 5 * mem::size_of::<usize>();
+
+impl Registers {
+    pub fn from_user(&self) -> bool {
+        self.cs & 0b11 != 0
+    }
+
+    // the stack pointer of the interrupted code
+    pub fn esp(&self) -> usize {
+        if self.from_user() {
+            self.user_esp
+        } else {
+            self.kernel_esp + KERNEL_FRAME
+        }
+    }
+
+    pub fn ss(&self) -> usize {
+        if self.from_user() {
+            self.user_ss
+        } else {
+            // exceptions in kernel mode don't change ss
+            let ss: u16;
+            unsafe { asm!("mov     %ss, $0" : "=r"(ss)) };
+            ss as usize
+        }
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn ss(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("stack-segment fault"); // 0xC
+// a register dump, the control registers are read when it's printed
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (cr0, cr2, cr3, cr4) =
+            unsafe { (cr0().bits(), cr2(), cr3(), cr4().bits()) };
+        writeln!(
+            f,
+            "eax: {:08x} ebx: {:08x} ecx: {:08x} edx: {:08x}",
+            self.eax,
+            self.ebx,
+            self.ecx,
+            self.edx,
+        )?;
+        writeln!(
+            f,
+            "esi: {:08x} edi: {:08x} ebp: {:08x} esp: {:08x}",
+            self.esi,
+            self.edi,
+            self.ebp,
+            self.esp(),
+        )?;
+        writeln!(
+            f,
+            "eip: {:08x} eflags: {:08x} cs: {:04x} ss: {:04x}",
+            self.eip,
+            self.eflags,
+            self.cs,
+            self.ss(),
+        )?;
+        write!(
+            f,
+            "cr0: {:08x} cr2: {:08x} cr3: {:08x} cr4: {:08x}",
+            cr0,
+            cr2,
+            cr3,
+            cr4,
+        )
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn gp(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("general protection fault"); // 0xD
+pub fn name(vector: usize) -> &'static str {
+    match vector {
+        0x0 => "divide-by-zero error",
+        0x1 => "debug",
+        0x2 => "non-maskable interrupt",
+        0x3 => "breakpoint",
+        0x4 => "overflow",
+        0x5 => "bound range exceeded",
+        0x6 => "invalid opcode",
+        0x7 => "device not available",
+        0x8 => "double fault",
+        0xa => "invalid tss",
+        0xb => "segment not present",
+        0xc => "stack-segment fault",
+        0xd => "general protection fault",
+        0xe => "page fault",
+        0x10 => "x87",
+        0x11 => "alignment check",
+        0x12 => "machine check",
+        0x13 => "simd floating-point exception",
+        0x14 => "virtualization exception",
+        0x1e => "security exception",
+        _ => "reserved exception",
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn pf(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("page fault"); // 0xE
+// decides what happens to the process a user mode exception came from, the
+// registers are restored from regs when it returns
+pub type UserHandler = fn(&mut Registers);
+
+static USER_HANDLER: Once<UserHandler> = Once::new();
+
+// without a user handler, user mode exceptions panic like kernel mode ones
+pub fn set_user_handler(handler: UserHandler) {
+    USER_HANDLER.call_once(|| handler);
 }
 
-pub unsafe extern "x86-interrupt" fn mf(_stack_frame: &ExceptionStackFrame) {
-    panic!("x87"); // 0x10
+// the entry stubs push a zero for exceptions without an error code, so every
+// exception reaches exception with the same frame
+global_asm!(
+    r#"
+.macro ISR name, vector, code=0
+.global isr_\name
+isr_\name:
+.if \code == 0
+        push    $0
+.endif
+        push    $\vector
+        pushal
+        push    %esp
+        cld
+        call    exception
+        add     $4, %esp
+        popal
+        add     $8, %esp
+        iret
+.endm
+
+ISR de, 0x0
+ISR db, 0x1
+ISR ni, 0x2
+ISR bp, 0x3
+ISR of, 0x4
+ISR br, 0x5
+ISR ud, 0x6
+ISR nm, 0x7
+ISR ts, 0xa, 1
+ISR np, 0xb, 1
+ISR ss, 0xc, 1
+ISR gp, 0xd, 1
+ISR pf, 0xe, 1
+ISR mf, 0x10
+ISR ac, 0x11, 1
+ISR mc, 0x12
+ISR xm, 0x13
+ISR ve, 0x14
+ISR sx, 0x1e, 1
+"#
+);
+
+extern "C" {
+    #[link_name = "isr_de"]
+    pub fn de(); // 0x0
+    #[link_name = "isr_db"]
+    pub fn db(); // 0x1
+    #[link_name = "isr_ni"]
+    pub fn ni(); // 0x2
+    #[link_name = "isr_bp"]
+    pub fn bp(); // 0x3
+    #[link_name = "isr_of"]
+    pub fn of(); // 0x4
+    #[link_name = "isr_br"]
+    pub fn br(); // 0x5
+    #[link_name = "isr_ud"]
+    pub fn ud(); // 0x6
+    #[link_name = "isr_nm"]
+    pub fn nm(); // 0x7
+    #[link_name = "isr_ts"]
+    pub fn ts(); // 0xA
+    #[link_name = "isr_np"]
+    pub fn np(); // 0xB
+    #[link_name = "isr_ss"]
+    pub fn ss(); // 0xC
+    #[link_name = "isr_gp"]
+    pub fn gp(); // 0xD
+    #[link_name = "isr_pf"]
+    pub fn pf(); // 0xE
+    #[link_name = "isr_mf"]
+    pub fn mf(); // 0x10
+    #[link_name = "isr_ac"]
+    pub fn ac(); // 0x11
+    #[link_name = "isr_mc"]
+    pub fn mc(); // 0x12
+    #[link_name = "isr_xm"]
+    pub fn xm(); // 0x13
+    #[link_name = "isr_ve"]
+    pub fn ve(); // 0x14
+    #[link_name = "isr_sx"]
+    pub fn sx(); // 0x1E
 }
 
-pub unsafe extern "x86-interrupt" fn ac(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("alignment check"); // 0x11
+#[no_mangle]
+pub unsafe extern "C" fn exception(regs: &mut Registers) {
+    match regs.vector {
+        0xa | 0xb | 0xc | 0xd => {
+            let error = SelectorError::new(regs.code);
+            unhandled(regs, format_args!(": {}", error))
+        }
+        0xe => page_fault(regs),
+        _ => unhandled(regs, format_args!("")),
+    }
 }
 
-pub unsafe extern "x86-interrupt" fn mc(_stack_frame: &ExceptionStackFrame) {
-    panic!("machine check"); // 0x12
+// user mode exceptions go to the user handler, everything else is a kernel
+// bug
+fn unhandled(regs: &mut Registers, detail: fmt::Arguments) {
+    if regs.from_user() {
+        if let Some(handler) = USER_HANDLER.try() {
+            return handler(regs);
+        }
+    }
+    panic!("{} ({:#x}){}\n{}", name(regs.vector), regs.vector, detail, regs);
 }
 
-pub unsafe extern "x86-interrupt" fn xm(_stack_frame: &ExceptionStackFrame) {
-    panic!("simd floating-point exception"); // 0x13
+// double faults switch to a task of their own, see kernel::init_gdt, so the
+// handler gets a fresh stack even when the fault came from an overflow
+//
+// the task switch pushes the error code on the new stack, the call makes it
+// df's argument
+global_asm!(
+    r#"
+.global df_task
+df_task:
+        call    df
+"#
+);
+
+extern "C" {
+    pub fn df_task();
 }
 
-pub unsafe extern "x86-interrupt" fn ve(_stack_frame: &ExceptionStackFrame) {
-    panic!("virtualization exception"); // 0x14
+#[no_mangle]
+pub unsafe extern "C" fn df(_code: usize) -> ! {
+    // the faulting state was saved to the cpu's tss by the task switch
+    let tss = ptr::read_volatile(kernel::tss());
+    let regs = Registers {
+        edi: tss.edi as usize,
+        esi: tss.esi as usize,
+        ebp: tss.ebp as usize,
+        kernel_esp: tss.esp as usize - KERNEL_FRAME,
+        ebx: tss.ebx as usize,
+        edx: tss.edx as usize,
+        ecx: tss.ecx as usize,
+        eax: tss.eax as usize,
+        vector: 0x8,
+        code: 0,
+        eip: tss.eip as usize,
+        cs: tss.cs as usize,
+        eflags: tss.eflags as usize,
+        user_esp: tss.esp as usize,
+        user_ss: tss.ss as usize,
+    };
+
+    let addr = Virtual::new(cr2());
+    if let Some(owner) = stack::overflowed(addr) {
+        panic!(
+            "kernel stack overflow in {} at {:08x}\n{}",
+            owner,
+            addr,
+            regs,
+        );
+    }
+    panic!("double fault (0x8)\n{}", regs);
 }
 
-pub unsafe extern "x86-interrupt" fn sx(
-    _stack_frame: &ExceptionStackFrame,
-    _code: usize,
-) {
-    panic!("security exception"); // 0x1E
+unsafe fn page_fault(regs: &mut Registers) {
+    let addr = Virtual::new(cr2());
+    let error = PageFaultError::from_bits_truncate(regs.code);
+
+    // only missing pages can be backed lazily
+    if !error.contains(PageFaultError::PRESENT) && lazy::resolve(addr, error) {
+        return;
+    }
+
+    // only writes to present pages can hit a copy-on-write page
+    if error.contains(PageFaultError::PRESENT | PageFaultError::WRITE)
+        && space::resolve(addr, error)
+    {
+        return;
+    }
+
+    if let Some(owner) = stack::overflowed(addr) {
+        panic!(
+            "kernel stack overflow in {} at {:08x}\n{}",
+            owner,
+            addr,
+            regs,
+        );
+    }
+
+    let mapping =
+        kernel::try_page_table().and_then(|table| table.translate(addr));
+    match mapping {
+        Some((phys, flags)) => unhandled(
+            regs,
+            format_args!(
+                " at {:08x}: {}, mapped to {:08x} with {:?}",
+                addr,
+                error,
+                phys,
+                flags,
+            ),
+        ),
+        None => unhandled(
+            regs,
+            format_args!(" at {:08x}: {}, not mapped", addr, error),
+        ),
+    }
 }
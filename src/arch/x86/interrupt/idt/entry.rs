@@ -64,6 +64,7 @@ bitflags! {
         const RING3 = 0b01100000;
         const INT_GATE = 0x0e;
         const TRP_GATE = 0x0f;
+        const TSK_GATE = 0x05;
     }
 }
 
@@ -90,6 +91,7 @@ impl From<RingLevel> for AttrFlags {
 pub enum Gate {
     Interrupt,
     Trap,
+    Task,
 }
 
 impl From<Gate> for AttrFlags {
@@ -97,6 +99,7 @@ impl From<Gate> for AttrFlags {
         match g {
             Gate::Interrupt => AttrFlags::INT_GATE,
             Gate::Trap => AttrFlags::TRP_GATE,
+            Gate::Task => AttrFlags::TSK_GATE,
         }
     }
 }
@@ -178,4 +181,12 @@ impl EntryBuilder {
         });
         self
     }
+
+    // a task gate switches to the task whose tss selector points to, it has
+    // no offset
+    pub fn task(mut self, tss: u16) -> EntryBuilder {
+        self.offset = Some(0);
+        self.selector = Some(tss);
+        self.gate(Gate::Task)
+    }
 }
@@ -56,4 +56,14 @@ impl<'a> Idt<'a> {
     pub fn new_interrupt_handler(&mut self, num: u8, isr: InterruptHandler) {
         self.new_default_handler(num, isr as *const ());
     }
+
+    // the handler runs as the task of the tss at selector tss
+    pub fn new_task_gate(&mut self, num: u8, tss: u16) {
+        let entry = EntryBuilder::new()
+            .present()
+            .task(tss)
+            .ring(RingLevel::Ring0)
+            .build();
+        self.new_handler(num, entry);
+    }
 }
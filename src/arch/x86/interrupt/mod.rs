@@ -14,8 +14,8 @@ pub struct ExceptionStackFrame {
 
 pub type InterruptHandler =
     unsafe extern "x86-interrupt" fn(&ExceptionStackFrame);
-pub type ExceptionHandler =
-    unsafe extern "x86-interrupt" fn(&ExceptionStackFrame, usize);
+// the entry stubs in exceptions, they save the registers for the handlers
+pub type ExceptionHandler = unsafe extern "C" fn();
 
 pub unsafe fn lidt(idtr: &Idtr) {
     asm!("lidtl   $0" : : "*m"(idtr) : "memory" : "volatile");
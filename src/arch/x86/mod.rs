@@ -1,17 +1,20 @@
-use core::mem;
-
-use multiboot2;
+use multiboot2::{self, ElfSectionFlags};
 use raw_cpuid::CpuId;
 
 use kmain;
+use acpi::{self, Acpi, Rsdp};
 
 pub mod interrupt;
 pub mod paging;
 pub mod segmentation;
 pub mod cpuid;
+pub mod smp;
 
-use mem::frame::{Allocator as FrameAllocator, FRAME_SIZE};
-use mem::page::Allocator as PageAllocator;
+use mem::frame::{self, Allocator as FrameAllocator, FRAME_SIZE};
+use mem::kmap;
+use mem::page::{Allocator as PageAllocator, HUGE_PAGE_SIZE};
+use mem::stack;
+use arch::paging::addr::Virtual;
 use arch::paging::table::{self, ActiveTable};
 
 global_asm!(
@@ -57,21 +60,30 @@ _start:
         call    _rust_start
 
 .section .bss
-.align 16
+.align 4096
+.global stack_guard
+stack_guard:
+.fill 4096, 1, 0
 stack_start:
 .fill 16384, 1, 0
 stack_end:
 "#
 );
 
+extern "C" {
+    static stack_guard: u8;
+}
+
 #[derive(Debug)]
 pub struct Kinfo {
     pub kernel_start: usize,
     pub kernel_end: usize,
     pub heap_start: usize,
     pub heap_end: usize,
-    pub free_memory: usize,
+    // in bytes, which can be more than 4 GiB with pae
+    pub free_memory: u64,
     pub cpuid: Option<CpuId>,
+    pub acpi: Option<Acpi>,
     _priv: (),
 }
 
@@ -103,7 +115,7 @@ pub unsafe extern "C" fn _rust_start(
         page_table,
         mb2_addr,
         kernel_start,
-        page_addr + 1024 * mem::size_of::<table::Entry>(),
+        page_addr + table::DIRECTORY_SIZE,
     ).unwrap_or_else(|| unreachable!());
     kmain(&kinfo);
 
@@ -123,54 +135,78 @@ fn kinit(
 
     KINIT.call_once(|| {
         let mb2 = unsafe { multiboot2::load(mb2_addr) };
+        // the tags are only read before the kernel is remapped
+        let rsdp = unsafe { Rsdp::from_multiboot(mb2.start_address()) };
 
-        let mut mem_min = kernel_end - kernel::KERNEL_BASE;
-        let mut mem_max = 0;
-        let mem_size;
-
-        // first get total available memory
         let memory_map = mb2.memory_map_tag()
             .unwrap_or_else(|| panic!("no memory map in mb2 header"));
 
-        for area in memory_map.memory_areas() {
-            let mut addr = area.start_address() as usize;
-            if addr > kernel::KERNEL_BASE {
-                addr -= kernel::KERNEL_BASE;
-            }
-            mem_min = mem_min.max(addr);
-            mem_max = mem_max.max(area.end_address() as usize);
-        }
+        let mut frame_alloc = FrameAllocator::new();
 
-        // then subtract elf sections
-        let elf_sections = mb2.elf_sections_tag()
-            .unwrap_or_else(|| panic!("no elf sections in mb2 header"));
-
-        for sect in elf_sections.sections() {
-            let mut addr = sect.start_address() as usize;
-            if addr > kernel::KERNEL_BASE {
-                addr -= kernel::KERNEL_BASE;
-            }
-            mem_min = mem_min.max(addr);
+        // only available areas are iterated, so reserved, acpi and defective
+        // memory never becomes a frame
+        for area in memory_map.memory_areas() {
+            frame_alloc.add_area(area.start_address()..area.end_address());
         }
 
-        // round to page boundaries
-        mem_min = (mem_min + FRAME_SIZE) & 0xffc00000;
-        mem_max = (mem_max & 0xffc00000) - 1;
-        mem_size = mem_max - mem_min + 1;
+        // the real mode ivt and bios data area
+        frame_alloc.reserve(0..FRAME_SIZE);
+        // the application processors start from here, below 1 MiB
+        frame_alloc.reserve(smp::TRAMPOLINE..smp::TRAMPOLINE + FRAME_SIZE);
+        // the kernel image, including the boot page directory
+        frame_alloc.reserve(
+            kernel_start - kernel::KERNEL_BASE
+                ..kernel_end - kernel::KERNEL_BASE,
+        );
+        // the multiboot information structure, still used below
+        frame_alloc.reserve(
+            mb2.start_address() - kernel::KERNEL_BASE
+                ..mb2.end_address() - kernel::KERNEL_BASE,
+        );
 
-        let frame_alloc = FrameAllocator::with_range(mem_min..mem_max);
         let page_alloc = PageAllocator::with_used(&page_table);
         kernel::set_allocator_pair(frame_alloc, page_alloc);
         kernel::set_page_table(page_table);
+        kmap::init();
+
+        let elf_sections = mb2.elf_sections_tag()
+            .unwrap_or_else(|| panic!("no elf sections in mb2 header"));
+        let sections = elf_sections
+            .sections()
+            .filter(|sect| sect.flags().contains(ElfSectionFlags::ALLOCATED))
+            .map(|sect| {
+                let start = sect.start_address() as usize;
+                let end = sect.end_address() as usize;
+                (start..end, sect.flags())
+            });
+
+        unsafe {
+            kernel::remap_kernel(kernel_start..kernel_end, sections);
+        }
 
-        let heap_start = kernel::KERNEL_BASE + mem_min;
-        let heap_end = heap_start + 2 * FRAME_SIZE;
+        // the huge pages mapped at boot end at the next huge page boundary
+        let kernel_end_phys = kernel_end - kernel::KERNEL_BASE;
+        let heap_start = kernel::KERNEL_BASE
+            + ((kernel_end_phys + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1));
+        let heap_end = heap_start + kernel::HEAP_SIZE;
 
         unsafe {
             kernel::init_heap(heap_start, heap_end);
             kernel::init_gdt();
             kernel::init_idt();
         }
+        frame::init_counts();
+
+        let acpi = acpi::init(rsdp);
+
+        // the page below the boot stack is left unmapped, so overflowing it
+        // faults instead of corrupting .bss
+        let guard = Virtual::new(unsafe { &stack_guard as *const _ as usize });
+        unsafe { kernel::page_table() }.unmap(guard).flush();
+        stack::register_guard(guard, "boot");
+
+        let free_frames = unsafe { kernel::frame_alloc().free() } as u64;
+        let free_memory = free_frames * FRAME_SIZE as u64;
 
         let cpuid = if cpuid::available() {
             Some(CpuId::new())
@@ -183,8 +219,9 @@ fn kinit(
             kernel_end,
             heap_start,
             heap_end,
-            free_memory: mem_size - (heap_end - heap_start),
+            free_memory,
             cpuid,
+            acpi,
             _priv: (),
         });
     });
@@ -194,63 +231,95 @@ fn kinit(
 
 pub mod kernel {
     use core::mem;
+    use core::ops::Range;
     use core::slice;
 
     use alloc::allocator::{Alloc, Layout};
+    use alloc::boxed::Box;
+
+    use multiboot2::ElfSectionFlags;
 
     use spin::{Mutex, MutexGuard, Once};
 
     use x86::shared::control_regs::{CR0_ENABLE_PAGING, CR0_WRITE_PROTECT,
-                                    CR4_ENABLE_PSE, cr0, cr0_write, cr4,
-                                    cr4_write};
+                                    CR4_ENABLE_PAE, CR4_ENABLE_PSE, cr0,
+                                    cr0_write, cr3, cr4, cr4_write};
 
-    use ALLOCATOR;
+    use {ALLOCATOR, SLAB};
 
     use arch::paging::addr::*;
-    use arch::paging::table::{self, ActiveTable, InactiveTable};
+    use arch::paging::table::{self, ActiveTable, EntryBuilder, InactiveTable,
+                              PageSize};
+    use arch::paging::tlb;
 
-    use arch::segmentation::{lgdt, reload_segments};
-    use arch::segmentation::gdt::{self, Gdt, Gdtr};
+    use arch::segmentation::{lgdt, ltr, reload_segments};
+    use arch::segmentation::gdt::{self, Gdt, Gdtr, Tss};
 
     use arch::interrupt::{exceptions, lidt};
     use arch::interrupt::idt::{self, Idt, Idtr};
 
+    use drivers::{irq, pic};
+
     use mem::frame::Allocator as FrameAllocator;
-    use mem::page::{Allocator as PageAllocator, PAGE_SIZE};
+    use mem::kmap;
+    use mem::page::{Allocator as PageAllocator, HUGE_PAGE_SIZE, PAGE_SIZE};
+    use mem::stack::Stack;
 
     pub const KERNEL_BASE: usize = 0xe0000000;
+    pub const HEAP_SIZE: usize = 256 * PAGE_SIZE;
+    // the kmap slots, right below the recursive mapping of either paging mode
+    pub const KMAP_BASE: usize = 0xff000000;
+    // device registers and firmware tables are mapped from here on
+    pub const MMIO_BASE: usize = 0xfd000000;
+    // kernel stacks are allocated from here on
+    pub const STACKS_BASE: usize = 0xfe000000;
 
     static FRAME_ALLOC: Once<Mutex<FrameAllocator>> = Once::new();
     static PAGE_ALLOC: Once<Mutex<PageAllocator>> = Once::new();
     static PAGE_TABLE: Once<Mutex<ActiveTable<'static>>> = Once::new();
 
+    // the kernel runs as the task of TSS, a double fault switches to the task
+    // of DOUBLE_FAULT_TSS, which runs on a stack of its own, application
+    // processors get both from init_ap_gdt
+    const TSS_SELECTOR: u16 = 0x18;
+    const DOUBLE_FAULT_SELECTOR: u16 = 0x20;
+    static TSS: Once<Tss> = Once::new();
+    static DOUBLE_FAULT_TSS: Once<Tss> = Once::new();
+    static DOUBLE_FAULT_STACK: Once<Stack> = Once::new();
+
     pub unsafe fn init_paging(addr: usize) -> ActiveTable<'static> {
         assert_has_not_been_called!("k::arch::kernel::init_paging can only be called from boot code @ _rust_start");
 
-        let page_map = slice::from_raw_parts_mut(addr as *mut _, 1024);
+        let page_map = slice::from_raw_parts_mut(
+            addr as *mut _,
+            table::DIRECTORY_ENTRIES,
+        );
 
         for entry in page_map.iter_mut() {
             *entry = table::Entry::empty()
         }
 
-        let size = addr + 1024 * mem::size_of::<table::Entry>();
+        let end = addr + table::DIRECTORY_SIZE;
         let mut page_map = InactiveTable::new(page_map);
 
-        // first four megabytes identity
-        page_map.default_map(Virtual::new(0), Physical::new(0));
-        // first four megabytes higher half
-        page_map.default_map(Virtual::new(KERNEL_BASE), Physical::new(0));
-        if (addr + size) & 0xffc00000 > 0 {
-            let page = (addr + size) & 0xffc00000;
-            page_map.default_map(
-                Virtual::new(KERNEL_BASE + page),
-                Physical::new(page),
-            );
+        // the kernel runs at its physical address until it jumped to the
+        // higher half, so every huge page up to the directory is mapped both
+        // there and at KERNEL_BASE
+        let huge_pages = (end + HUGE_PAGE_SIZE - 1) / HUGE_PAGE_SIZE;
+        for page in 0..huge_pages {
+            let addr = page * HUGE_PAGE_SIZE;
+            let phys = Physical::new(addr as PhysAddr);
+            page_map.default_map(Virtual::new(addr), phys);
+            page_map.default_map(Virtual::new(KERNEL_BASE + addr), phys);
         }
 
         let mut page_map = page_map.load();
 
-        cr4_write(cr4() | CR4_ENABLE_PSE);
+        if cfg!(feature = "pae") {
+            cr4_write(cr4() | CR4_ENABLE_PAE);
+        } else {
+            cr4_write(cr4() | CR4_ENABLE_PSE);
+        }
         cr0_write(cr0() | CR0_ENABLE_PAGING | CR0_WRITE_PROTECT);
         // add KERNEL_BASE to stack pointer
         // add KERNEL_BASE to base pointer
@@ -276,7 +345,16 @@ pub mod kernel {
              " : : "i"(KERNEL_BASE) : "eax" "ebx" "memory" : "volatile"
         );
 
-        page_map.unmap(Virtual::new(0));
+        for page in 0..huge_pages {
+            page_map.unmap(Virtual::new(page * HUGE_PAGE_SIZE)).flush();
+        }
+
+        // kernel pages are mapped global from here on
+        tlb::enable_global_pages();
+        // only with pae, the kernel's data is mapped non-executable once this
+        // succeeded
+        table::enable_no_execute();
+
         page_map
     }
 
@@ -293,6 +371,9 @@ pub mod kernel {
         PAGE_TABLE.try().unwrap().lock()
     }
 
+    // the try_ accessors are for interrupt and fault handlers, which may
+    // interrupt code that holds the lock, they get None then and have to give
+    // up rather than deadlock
     pub fn try_page_table() -> Option<MutexGuard<'static, ActiveTable<'static>>>
     {
         PAGE_TABLE.try().and_then(|table| table.try_lock())
@@ -314,6 +395,138 @@ pub mod kernel {
         PAGE_ALLOC.try().and_then(|alloc| alloc.try_lock())
     }
 
+    // replaces the huge pages that hold the kernel with page tables, so that
+    // every kernel section gets its own permissions: read-only text and
+    // rodata, writable data and bss
+    //
+    // only text is executable with pae, classic paging has no execute
+    // permission, low memory, the boot page directory and free frames all
+    // stay writable
+    pub unsafe fn remap_kernel<I>(kernel: Range<usize>, sections: I)
+    where
+        I: Iterator<Item = (Range<usize>, ElfSectionFlags)>,
+    {
+        assert_has_not_been_called!(
+            "k::arch::kernel::remap_kernel can only be called once"
+        );
+
+        // init_paging mapped the huge pages from 0 to the end of the kernel
+        let huge_pages =
+            (kernel.end - KERNEL_BASE + HUGE_PAGE_SIZE - 1) / HUGE_PAGE_SIZE;
+        for idx in 0..huge_pages {
+            let addr = idx * HUGE_PAGE_SIZE;
+            let phys = Physical::new(addr as PhysAddr);
+            let table = split_huge_page(phys, &kernel);
+            // invlpg drops the cached huge page and directory entry, the
+            // kernel pages are then fetched from the new table
+            page_table()
+                .map(
+                    Virtual::new(KERNEL_BASE + addr),
+                    EntryBuilder::new()
+                        .addr(table)
+                        .present()
+                        .read_write()
+                        .page_size(PageSize::Normal)
+                        .build(),
+                )
+                .flush();
+        }
+
+        let mut table = page_table();
+        let mut batch = tlb::Batch::new();
+        for (range, flags) in sections {
+            // sections that aren't in the higher half aren't loaded there
+            if range.start < KERNEL_BASE {
+                continue;
+            }
+            assert!(
+                range.start >= kernel.start && range.end <= kernel.end,
+                "kernel section {:08x}-{:08x} is outside of the kernel",
+                range.start,
+                range.end,
+            );
+
+            let start = range.start / PAGE_SIZE;
+            let end = (range.end + PAGE_SIZE - 1) / PAGE_SIZE;
+            for idx in start..end {
+                let virt = idx * PAGE_SIZE;
+                let mut builder = EntryBuilder::new()
+                    .addr(Physical::new((virt - KERNEL_BASE) as PhysAddr))
+                    .present()
+                    .global()
+                    .page_size(PageSize::Normal);
+                if flags.contains(ElfSectionFlags::WRITABLE) {
+                    builder = builder.read_write();
+                }
+                if !flags.contains(ElfSectionFlags::EXECUTABLE) {
+                    builder = builder.no_execute();
+                }
+                batch.add(
+                    table
+                        .remap_page(Virtual::new(virt), builder.build())
+                        .unwrap_or_else(|| unreachable!()),
+                );
+            }
+        }
+
+        // the boot page directory follows the image, but isn't a section
+        let root = table::active_root();
+        for frame in 0..table::ROOT_FRAMES {
+            let phys = root + frame * PAGE_SIZE;
+            let virt = Virtual::new(KERNEL_BASE + phys.into_inner() as usize);
+            batch.add(
+                table
+                    .remap_page(virt, writable_page(phys))
+                    .unwrap_or_else(|| unreachable!()),
+            );
+        }
+        batch.flush();
+    }
+
+    // a page table with the frames of the huge page at phys, writable and not
+    // executable, but the kernel's pages stay executable, it keeps running
+    // from them until remap_kernel gave them their own permissions
+    unsafe fn split_huge_page(
+        phys: Physical,
+        kernel: &Range<usize>,
+    ) -> Physical {
+        let frame = frame_alloc()
+            .allocate()
+            .unwrap_or_else(|| panic!("couldn't allocate a kernel page table"))
+            .leak();
+        let mut slot = kmap::kmap(frame).unwrap_or_else(|| {
+            panic!("no kmap slot for the kernel page table")
+        });
+
+        for (idx, entry) in slot.entries().iter_mut().enumerate() {
+            let addr = phys + idx * PAGE_SIZE;
+            let virt = KERNEL_BASE + addr.into_inner() as usize;
+            *entry = if virt < kernel.end && virt + PAGE_SIZE > kernel.start {
+                EntryBuilder::new()
+                    .addr(addr)
+                    .present()
+                    .read_write()
+                    .global()
+                    .page_size(PageSize::Normal)
+                    .build()
+            } else {
+                writable_page(addr)
+            };
+        }
+        frame
+    }
+
+    fn writable_page(phys: Physical) -> table::Entry {
+        EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .global()
+            .no_execute()
+            .page_size(PageSize::Normal)
+            .build()
+    }
+
     pub unsafe fn init_heap(heap_start: usize, heap_end: usize) {
         static HEAP: Once<()> = Once::new();
 
@@ -330,29 +543,32 @@ pub mod kernel {
             let mut page_alloc = page_alloc();
             let mut frame_alloc = frame_alloc();
 
-            let pages = heap_size >> 22;
+            let pages = heap_size / PAGE_SIZE;
 
             for i in 0..pages {
-                // these will never be freed anyway
-                let page = page_alloc.allocate_at(Virtual::new(heap_start));
+                let addr = heap_start + i * PAGE_SIZE;
+                let page = page_alloc.allocate_at(Virtual::new(addr));
                 let frame = frame_alloc.allocate();
                 assert!(
-                    page.is_some(),
-                    "couldn't allocate {}-th heap page at {}",
+                    page.as_ref().map_or(false, |page| *page.addr() == addr),
+                    "couldn't allocate {}-th heap page at {:x}",
                     i,
-                    heap_start,
+                    addr,
                 );
                 assert!(
                     frame.is_some(),
                     "couldn't allocate {}-th heap frame",
                     i,
                 );
-                let page = page.unwrap();
-                let frame = frame.unwrap();
-                page_table.default_map(*page.addr(), *frame.addr());
+                // the heap is never freed, so neither are its pages
+                let page = page.unwrap().leak();
+                let frame = frame.unwrap().leak();
+                page_table
+                    .global_map_page(page, frame, &mut frame_alloc)
+                    .flush();
             }
 
-            ALLOCATOR.lock().init(heap_start, heap_size);
+            ALLOCATOR.init(heap_start, heap_size);
         });
     }
 
@@ -361,52 +577,111 @@ pub mod kernel {
         static GDT: Once<Gdt> = Once::new();
 
         let gdt = GDT.call_once(|| {
-            let len = 8;
-            let ptr = (&ALLOCATOR)
-                .alloc(Layout::from_size_align_unchecked(
-                    len * mem::size_of::<gdt::Entry>(),
-                    mem::size_of::<gdt::Entry>(),
-                ))
-                .unwrap();
-            let table = slice::from_raw_parts_mut(ptr as *mut _, len);
-
-            for entry in table.iter_mut() {
-                *entry = gdt::Entry::empty()
-            }
-
-            let mut gdt = Gdt::with_table(table);
-            gdt.new_entry(
-                0x8,
-                gdt::EntryBuilder::new()
-                    .base(0)
-                    .limit(0xfffff)
-                    .granularity(gdt::Granularity::Page)
-                    .size(32)
-                    .present()
-                    .ring(gdt::RingLevel::Ring0)
-                    .executable()
-                    .read_write()
-                    .build(),
-            );
-            gdt.new_entry(
-                0x10,
-                gdt::EntryBuilder::new()
-                    .base(0)
-                    .limit(0xfffff)
-                    .granularity(gdt::Granularity::Page)
-                    .size(32)
-                    .present()
-                    .ring(gdt::RingLevel::Ring0)
-                    .read_write()
-                    .build(),
-            );
-            gdt
+            new_gdt(TSS.call_once(Tss::new), double_fault_tss())
         });
-
         let gdtr = GDTR.call_once(|| gdt.gdtr());
 
         lgdt(gdtr);
         reload_segments(0x8, 0x10);
+        ltr(TSS_SELECTOR);
+    }
+
+    // gives an application processor a gdt, a tss and a double fault task of
+    // its own, two cpus can't share the task, the second double fault would
+    // find it busy and run on the first one's stack
+    pub unsafe fn init_ap_gdt() {
+        let stack = Stack::new("double fault").unwrap_or_else(|| {
+            panic!("no memory left for the double fault stack")
+        });
+        let tss = &*Box::into_raw(Box::new(Tss::new()));
+        let df_tss = &*Box::into_raw(Box::new(double_fault_task(&stack)));
+        // the task runs on it for as long as the cpu does
+        mem::forget(stack);
+        let gdt = new_gdt(tss, df_tss);
+
+        lgdt(&gdt.gdtr());
+        reload_segments(0x8, 0x10);
+        ltr(TSS_SELECTOR);
+    }
+
+    // flat code and data segments, the task of tss and the double fault task
+    // of df_tss, with room for the per-cpu segment
+    unsafe fn new_gdt(tss: &'static Tss, df_tss: &'static Tss) -> Gdt<'static> {
+        let len = 8;
+        let ptr = (&SLAB)
+            .alloc(Layout::from_size_align_unchecked(
+                len * mem::size_of::<gdt::Entry>(),
+                mem::size_of::<gdt::Entry>(),
+            ))
+            .unwrap();
+        let table = slice::from_raw_parts_mut(ptr as *mut _, len);
+
+        for entry in table.iter_mut() {
+            *entry = gdt::Entry::empty()
+        }
+
+        let mut gdt = Gdt::with_table(table);
+        gdt.new_entry(
+            0x8,
+            gdt::EntryBuilder::new()
+                .base(0)
+                .limit(0xfffff)
+                .granularity(gdt::Granularity::Page)
+                .size(32)
+                .present()
+                .ring(gdt::RingLevel::Ring0)
+                .executable()
+                .read_write()
+                .build(),
+        );
+        gdt.new_entry(
+            0x10,
+            gdt::EntryBuilder::new()
+                .base(0)
+                .limit(0xfffff)
+                .granularity(gdt::Granularity::Page)
+                .size(32)
+                .present()
+                .ring(gdt::RingLevel::Ring0)
+                .read_write()
+                .build(),
+        );
+        gdt.new_tss(TSS_SELECTOR, tss);
+        gdt.new_tss(DOUBLE_FAULT_SELECTOR, df_tss);
+        gdt
+    }
+
+    // the bootstrap processor's double fault task
+    fn double_fault_tss() -> &'static Tss {
+        let stack = DOUBLE_FAULT_STACK.call_once(|| {
+            Stack::new("double fault").unwrap_or_else(|| {
+                panic!("no memory left for the double fault stack")
+            })
+        });
+        DOUBLE_FAULT_TSS.call_once(|| double_fault_task(stack))
+    }
+
+    // a task that handles double faults on stack
+    fn double_fault_task(stack: &Stack) -> Tss {
+        let mut tss = Tss::new();
+        tss.set_entry(
+            exceptions::df_task as usize,
+            stack.top().into_inner(),
+            0x8,
+            0x10,
+            unsafe { cr3() },
+        );
+        tss
+    }
+
+    // the state of the task this cpu runs, the cpu saves it there when a
+    // double fault switches tasks
+    //
+    // every cpu has a tss of its own, it's found through the gdt, which the
+    // task switch leaves loaded
+    pub unsafe fn tss() -> &'static Tss {
+        let gdt = Gdt::current();
+        &*(gdt.entry(TSS_SELECTOR).base() as *const Tss)
     }
 
     pub unsafe fn init_idt() {
@@ -415,7 +690,7 @@ pub mod kernel {
 
         let idt = IDT.call_once(|| {
             let len = 256;
-            let ptr = (&ALLOCATOR)
+            let ptr = (&SLAB)
                 .alloc(Layout::from_size_align_unchecked(
                     len * mem::size_of::<idt::Entry>(),
                     mem::size_of::<idt::Entry>(),
@@ -429,27 +704,36 @@ pub mod kernel {
 
             let mut idt = Idt::with_table(table);
 
-            idt.new_interrupt_handler(0x0, exceptions::de);
-            idt.new_interrupt_handler(0x1, exceptions::db);
-            idt.new_interrupt_handler(0x2, exceptions::ni);
-            idt.new_interrupt_handler(0x3, exceptions::bp);
-            idt.new_interrupt_handler(0x4, exceptions::of);
-            idt.new_interrupt_handler(0x5, exceptions::br);
-            idt.new_interrupt_handler(0x6, exceptions::ud);
-            idt.new_interrupt_handler(0x7, exceptions::nm);
-            idt.new_exception_handler(0x8, exceptions::df);
+            idt.new_exception_handler(0x0, exceptions::de);
+            idt.new_exception_handler(0x1, exceptions::db);
+            idt.new_exception_handler(0x2, exceptions::ni);
+            idt.new_exception_handler(0x3, exceptions::bp);
+            idt.new_exception_handler(0x4, exceptions::of);
+            idt.new_exception_handler(0x5, exceptions::br);
+            idt.new_exception_handler(0x6, exceptions::ud);
+            idt.new_exception_handler(0x7, exceptions::nm);
+            idt.new_task_gate(0x8, DOUBLE_FAULT_SELECTOR);
             idt.new_exception_handler(0xa, exceptions::ts);
             idt.new_exception_handler(0xb, exceptions::np);
             idt.new_exception_handler(0xc, exceptions::ss);
             idt.new_exception_handler(0xd, exceptions::gp);
             idt.new_exception_handler(0xe, exceptions::pf);
-            idt.new_interrupt_handler(0x10, exceptions::mf);
+            idt.new_exception_handler(0x10, exceptions::mf);
             idt.new_exception_handler(0x11, exceptions::ac);
-            idt.new_interrupt_handler(0x12, exceptions::mc);
-            idt.new_interrupt_handler(0x13, exceptions::xm);
-            idt.new_interrupt_handler(0x14, exceptions::ve);
+            idt.new_exception_handler(0x12, exceptions::mc);
+            idt.new_exception_handler(0x13, exceptions::xm);
+            idt.new_exception_handler(0x14, exceptions::ve);
             idt.new_exception_handler(0x1e, exceptions::sx);
 
+            for (line, stub) in irq::STUBS.iter().enumerate() {
+                idt.new_interrupt_handler(pic::OFFSET + line as u8, *stub);
+            }
+            // a disabled pic and the apic's spurious vector
+            for line in 0..irq::LINES as u8 {
+                let vector = pic::DISABLED_OFFSET + line;
+                idt.new_interrupt_handler(vector, pic::ignore);
+            }
+
             idt.new_interrupt_handler(0x80, ::syscall::handler);
 
             idt
@@ -2,17 +2,23 @@ use core::fmt::{self, LowerHex};
 use core::ops::{Add, BitAnd, Shl, Shr};
 use core::cmp::Ordering;
 
+// pae's entries take 64-bit physical addresses, which reach past 4 GiB
+#[cfg(feature = "pae")]
+pub type PhysAddr = u64;
+#[cfg(not(feature = "pae"))]
+pub type PhysAddr = usize;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Physical(usize);
+pub struct Physical(PhysAddr);
 
 impl Physical {
     #[inline]
-    pub fn new(addr: usize) -> Physical {
+    pub fn new(addr: PhysAddr) -> Physical {
         Physical(addr)
     }
 
     #[inline]
-    pub fn into_inner(self) -> usize {
+    pub fn into_inner(self) -> PhysAddr {
         self.0
     }
 }
@@ -44,8 +50,8 @@ impl LowerHex for Virtual {
     }
 }
 
-impl PartialEq<usize> for Physical {
-    fn eq(&self, rhs: &usize) -> bool {
+impl PartialEq<PhysAddr> for Physical {
+    fn eq(&self, rhs: &PhysAddr) -> bool {
         self.0.eq(rhs)
     }
 }
@@ -56,8 +62,8 @@ impl PartialEq<usize> for Virtual {
     }
 }
 
-impl PartialOrd<usize> for Physical {
-    fn partial_cmp(&self, rhs: &usize) -> Option<Ordering> {
+impl PartialOrd<PhysAddr> for Physical {
+    fn partial_cmp(&self, rhs: &PhysAddr) -> Option<Ordering> {
         self.0.partial_cmp(rhs)
     }
 }
@@ -86,14 +92,23 @@ macro impl_op {
                 $type :: new ( (self.0).$fun ( rhs ) )
             }
         }
+    },
+    ($type:ident, $rhs:ident as $inner:ident, $op:ty, $fun:ident) => {
+        impl $op for $type {
+            type Output = $type;
+
+            fn $fun(self, rhs: $rhs) -> $type {
+                $type :: new ( (self.0).$fun ( rhs as $inner ) )
+            }
+        }
     }
 }
 
 impl_op!(Physical, Add, add);
 impl_op!(Physical, BitAnd, bitand);
 
-impl_op!(Physical, usize, Add<usize>, add);
-impl_op!(Physical, usize, BitAnd<usize>, bitand);
+impl_op!(Physical, usize as PhysAddr, Add<usize>, add);
+impl_op!(Physical, PhysAddr, BitAnd<PhysAddr>, bitand);
 impl_op!(Physical, u8, Shl<u8>, shl);
 impl_op!(Physical, u8, Shr<u8>, shr);
 
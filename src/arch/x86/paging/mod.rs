@@ -1,2 +1,3 @@
 pub mod addr;
 pub mod table;
+pub mod tlb;
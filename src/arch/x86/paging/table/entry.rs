@@ -1,30 +1,129 @@
-// this os only uses huge pages
-// it makes my life simpler
+// a directory entry either maps a huge page directly or points to a page
+// table, whose entries map 4 KiB pages
+//
+// classic entries are 32 bits and huge pages 4 MiB, pae and long mode entries
+// are 64 bits, huge pages 2 MiB and the top bit forbids execution
 use core::fmt;
+use core::mem;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+use x86::shared::msr::{rdmsr, wrmsr, IA32_EFER};
+
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+use raw_cpuid::CpuId;
+
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+use arch::cpuid;
 use arch::paging::addr::*;
-use mem::frame::FRAME_SIZE;
+use mem::frame::{FRAME_SIZE, HUGE_FRAME_SIZE};
+
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+type Raw = u32;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+type Raw = u64;
+
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+const ADDR_MASK: Raw = 0xfffff000;
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+const HUGE_ADDR_MASK: Raw = 0xffc00000;
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+const FLAGS_MASK: Raw = 0x00000fff;
+
+// 52 bits of physical address
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+const ADDR_MASK: Raw = 0x000ffffffffff000;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+const HUGE_ADDR_MASK: Raw = 0x000fffffffe00000;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+const FLAGS_MASK: Raw = 0x8000000000000fff;
+
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+const EFER_NXE: u64 = 1 << 11;
+
+// the no-execute bit is reserved until the cpu has been told to honour it, so
+// it's only set in entries after enable_no_execute succeeded
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+static NO_EXECUTE: AtomicBool = ATOMIC_BOOL_INIT;
+
+// enables the no-execute bit if the cpu supports it, returns whether it did
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+pub unsafe fn enable_no_execute() -> bool {
+    let supported = cpuid::available()
+        && CpuId::new()
+            .get_extended_function_info()
+            .map_or(false, |info| info.has_execute_disable());
+
+    if supported {
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE);
+        NO_EXECUTE.store(true, Ordering::SeqCst);
+    }
+    supported
+}
+
+// classic paging has no no-execute bit, every present page is executable
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+pub unsafe fn enable_no_execute() -> bool {
+    false
+}
+
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+pub fn no_execute_enabled() -> bool {
+    NO_EXECUTE.load(Ordering::Relaxed)
+}
+
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+pub fn no_execute_enabled() -> bool {
+    false
+}
 
 #[repr(C, packed)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Entry {
-    flags: u16,
-    addr: u16,
+    inner: Raw,
 }
 
 impl Entry {
     // the compiler thinks this method is unused, but it is actually used
     #[allow(dead_code)]
     pub(crate) const fn empty() -> Entry {
-        Entry { addr: 0, flags: 0 }
+        Entry { inner: 0 }
+    }
+
+    // the full physical address, which can be above 4 GiB with pae
+    pub fn address(&self) -> u64 {
+        let mask = match self.page_size() {
+            PageSize::Normal => ADDR_MASK,
+            PageSize::Huge => HUGE_ADDR_MASK,
+        };
+        (self.inner & mask) as u64
     }
 
     pub fn into_physical(&self) -> Physical {
-        Physical::new((self.addr as usize) << 16)
+        Physical::new(self.address() as PhysAddr)
+    }
+
+    pub fn flags(&self) -> Flags {
+        Flags::from_bits_truncate((self.inner & FLAGS_MASK) as u64)
+    }
+
+    pub fn page_size(&self) -> PageSize {
+        if self.flags().contains(Flags::SIZE) {
+            PageSize::Huge
+        } else {
+            PageSize::Normal
+        }
     }
 
     pub fn is_used(&self) -> bool {
-        self.flags & Flags::PRESENT.bits() != 0
+        self.flags().contains(Flags::PRESENT)
+    }
+
+    // only meaningful for directory entries, in a page table the same bit
+    // selects the page attribute table
+    pub fn is_huge(&self) -> bool {
+        self.is_used() && self.page_size() == PageSize::Huge
     }
 }
 
@@ -33,10 +132,10 @@ impl fmt::Debug for Entry {
         unsafe {
             write!(
                 f,
-                "Entry {} addr: {:08x}, flags: {:04x}, {}",
+                "Entry {} addr: {:08x}, flags: {:03x}, {}",
                 '{',
-                (self.addr as u32) << 16,
-                self.flags,
+                self.inner & ADDR_MASK,
+                self.inner & FLAGS_MASK,
                 '}',
             )
         }
@@ -45,13 +144,19 @@ impl fmt::Debug for Entry {
 
 impl fmt::LowerHex for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        unsafe { write!(f, "{:04x}{:04x}", self.flags, self.addr,) }
+        let width = mem::size_of::<Raw>() * 2;
+        unsafe { write!(f, "{:01$x}", self.inner, width) }
     }
 }
 
 bitflags! {
     #[derive(Default)]
-    pub struct Flags: u16 {
+    pub struct Flags: u64 {
+        // only with pae and in long mode
+        const NO_EXECUTE = 1 << 63;
+        // ignored by the mmu, the kernel keeps track of its frames with them
+        const COPY_ON_WRITE = 0b10000000000;
+        const OWNED = 0b01000000000;
         const GLOBAL = 0b100000000;
         const SIZE = 0b010000000;
         const DIRTY = 0b001000000;
@@ -70,6 +175,15 @@ pub enum PageSize {
     Huge,
 }
 
+impl PageSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            PageSize::Normal => FRAME_SIZE,
+            PageSize::Huge => HUGE_FRAME_SIZE,
+        }
+    }
+}
+
 impl From<PageSize> for Flags {
     fn from(size: PageSize) -> Flags {
         match size {
@@ -94,14 +208,18 @@ impl EntryBuilder {
     }
 
     pub fn build(self) -> Entry {
-        let addr = self.addr.unwrap().into_inner();
+        let addr = self.addr.unwrap().into_inner() as Raw;
         let flags = self.flags.unwrap();
-        if addr & (FRAME_SIZE - 1) != 0 {
+        let size = if flags.contains(Flags::SIZE) {
+            PageSize::Huge
+        } else {
+            PageSize::Normal
+        };
+        if addr & (size.bytes() as Raw - 1) != 0 {
             panic!("page directory entry address must be page-aligned");
         }
         Entry {
-            addr: (addr >> 16) as u16,
-            flags: flags.bits(),
+            inner: addr | flags.bits() as Raw,
         }
     }
 
@@ -153,6 +271,21 @@ impl EntryBuilder {
         self
     }
 
+    // a no-op with classic paging or if the cpu doesn't support it
+    pub fn no_execute(mut self) -> EntryBuilder {
+        if no_execute_enabled() {
+            self.flags =
+                Some(self.flags.unwrap_or_default() | Flags::NO_EXECUTE);
+        }
+        self
+    }
+
+    // sets flags as they are, no-execute included
+    pub fn flags(mut self, flags: Flags) -> EntryBuilder {
+        self.flags = Some(self.flags.unwrap_or_default() | flags);
+        self
+    }
+
     pub fn page_size(mut self, size: PageSize) -> EntryBuilder {
         let bit = Flags::from(size);
         self.flags = Some({
@@ -1,25 +1,272 @@
+use core::mem;
 use core::slice;
-use core::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut, Range};
 
 use x86::shared::control_regs::{cr3, cr3_write};
 
 use arch::paging::addr::*;
-use mem::frame::FRAME_SIZE;
+use arch::paging::tlb::Flush;
+use mem::frame::{Allocator as FrameAllocator, FRAME_SIZE, HUGE_FRAME_SIZE};
+use mem::kmap;
+use macros::*;
 
 pub mod entry;
 pub use self::entry::*;
 
+// entries in a page table
+#[cfg(not(feature = "pae"))]
+pub const ENTRIES: usize = 1024;
+#[cfg(feature = "pae")]
+pub const ENTRIES: usize = 512;
+
+// pae splits the directory in four, one per gigabyte, which are pointed to by
+// the page directory pointer table in cr3
+//
+// the four directories have to be physically contiguous, so they can be
+// handled as a single directory of 2048 entries, and the pointer table lives
+// in the page after them
+#[cfg(not(feature = "pae"))]
+const DIRECTORIES: usize = 1;
+#[cfg(feature = "pae")]
+const DIRECTORIES: usize = 4;
+
+#[cfg(not(feature = "pae"))]
+const DIRECTORY_SHIFT: usize = 22;
+#[cfg(feature = "pae")]
+const DIRECTORY_SHIFT: usize = 21;
+
+pub const DIRECTORY_ENTRIES: usize = DIRECTORIES * FRAME_SIZE / ENTRY_SIZE;
+
+// bytes an inactive table needs, including the pointer table with pae
+#[cfg(not(feature = "pae"))]
+pub const DIRECTORY_SIZE: usize = FRAME_SIZE;
+#[cfg(feature = "pae")]
+pub const DIRECTORY_SIZE: usize = (DIRECTORIES + 1) * FRAME_SIZE;
+
+const ENTRY_SIZE: usize = mem::size_of::<Entry>();
+
+// the last directory entries point back to the directories, so the page
+// tables of the active directory show up at RECURSIVE_BASE and the directory
+// itself at DIRECTORY
+//
+// that's 0xffc00000 and 0xfffff000 with classic paging, 0xff800000 and
+// 0xffffc000 with pae
+const RECURSIVE_IDX: usize = DIRECTORY_ENTRIES - DIRECTORIES;
+const RECURSIVE_BASE: usize = RECURSIVE_IDX << DIRECTORY_SHIFT;
+const DIRECTORY: usize = RECURSIVE_BASE + (RECURSIVE_IDX << 12);
+
+// the directory is level 2, page tables are level 1
+pub const LEVELS: usize = 2;
+
+// an address space's root is the directory, the pointer table comes after it
+pub const ROOT_ENTRIES: usize = DIRECTORY_ENTRIES;
+pub const ROOT_FRAMES: usize = DIRECTORY_SIZE / FRAME_SIZE;
+
+#[inline]
+fn directory_index(virt: Virtual) -> usize {
+    virt.into_inner() >> DIRECTORY_SHIFT
+}
+
+#[inline]
+fn table_index(virt: Virtual) -> usize {
+    (virt.into_inner() >> 12) & (ENTRIES - 1)
+}
+
+#[inline]
+pub fn index(virt: Virtual, level: usize) -> usize {
+    match level {
+        1 => table_index(virt),
+        2 => directory_index(virt),
+        _ => panic!("there's no paging level {}", level),
+    }
+}
+
+#[inline]
+pub fn is_recursive(idx: usize) -> bool {
+    idx >= RECURSIVE_IDX
+}
+
+// where the page tables of a directory can be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Tables {
+    // through the recursive entry, only valid for the active directory
+    Recursive,
+    // at their physical address plus an offset
+    Offset(usize),
+}
+
 struct Table<'a> {
     inner: &'a mut [Entry],
+    tables: Tables,
 }
 
 impl<'a> Table<'a> {
-    pub unsafe fn new(inner: &'a mut [Entry]) -> Table<'a> {
-        Table { inner }
+    pub unsafe fn new(inner: &'a mut [Entry], tables: Tables) -> Table<'a> {
+        Table { inner, tables }
+    }
+
+    fn table_ptr(&self, idx: usize) -> *mut Entry {
+        let addr = match self.tables {
+            Tables::Recursive => RECURSIVE_BASE + (idx << 12),
+            Tables::Offset(offset) => {
+                self.inner[idx].into_physical().into_inner() as usize + offset
+            }
+        };
+        addr as *mut _
+    }
+
+    fn table(&self, idx: usize) -> Option<&[Entry]> {
+        let entry = self.inner[idx];
+        if entry.is_used() && !entry.is_huge() {
+            Some(unsafe { slice::from_raw_parts(self.table_ptr(idx), ENTRIES) })
+        } else {
+            None
+        }
+    }
+
+    fn table_mut(&mut self, idx: usize) -> Option<&mut [Entry]> {
+        let entry = self.inner[idx];
+        if entry.is_used() && !entry.is_huge() {
+            let ptr = self.table_ptr(idx);
+            Some(unsafe { slice::from_raw_parts_mut(ptr, ENTRIES) })
+        } else {
+            None
+        }
+    }
+
+    // a table for user pages has to allow user access itself, the leaf
+    // entries decide what's actually allowed
+    fn table_or_create(
+        &mut self,
+        idx: usize,
+        user: bool,
+        frames: &mut FrameAllocator,
+    ) -> &mut [Entry] {
+        assert!(
+            !self.inner[idx].is_huge(),
+            "{:08x} is already mapped by a huge page",
+            idx << DIRECTORY_SHIFT,
+        );
+
+        let entry = self.inner[idx];
+        if !entry.is_used() {
+            // page tables are never freed, not even when they become empty
+            let frame = frames
+                .allocate()
+                .unwrap_or_else(|| panic!("out of frames for page tables"));
+            let mut builder = EntryBuilder::new()
+                .addr(frame.leak())
+                .present()
+                .read_write()
+                .page_size(PageSize::Normal);
+            if user {
+                builder = builder.user();
+            }
+            self.inner[idx] = builder.build();
+
+            for entry in self.table_mut(idx).unwrap().iter_mut() {
+                *entry = Entry::empty();
+            }
+        } else if user && !entry.flags().contains(Flags::USER) {
+            self.inner[idx] = EntryBuilder::new()
+                .addr(entry.into_physical())
+                .flags(entry.flags() | Flags::USER)
+                .build();
+        }
+
+        self.table_mut(idx).unwrap()
+    }
+
+    // returns the entry that maps virt, either from the directory or from a
+    // page table
+    fn entry(&self, virt: Virtual) -> Option<Entry> {
+        let idx = directory_index(virt);
+        if self.inner[idx].is_huge() {
+            return Some(self.inner[idx]);
+        }
+
+        self.table(idx).and_then(|table| {
+            let entry = table[table_index(virt)];
+            if entry.is_used() {
+                Some(entry)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn translate(&self, virt: Virtual) -> Option<(Physical, Flags)> {
+        self.entry(virt).map(|entry| {
+            let offset = virt.into_inner() & (entry.page_size().bytes() - 1);
+            (entry.into_physical() + offset, entry.flags())
+        })
+    }
+
+    pub fn mappings<'t>(&'t self, range: Range<Virtual>) -> Mappings<'t, 'a> {
+        Mappings {
+            table: self,
+            next: Some(range.start.into_inner()),
+            end: range.end.into_inner(),
+        }
+    }
+
+    pub fn dump(&self) {
+        for (idx, entry) in self.inner.iter().enumerate() {
+            if !entry.is_used() {
+                continue;
+            }
+
+            let virt = idx << DIRECTORY_SHIFT;
+            if is_recursive(idx) && self.tables == Tables::Recursive {
+                kprintln!(
+                    "{:08x} recursive -> {:08x}",
+                    virt,
+                    entry.into_physical(),
+                );
+                continue;
+            }
+
+            if entry.is_huge() {
+                kprintln!(
+                    "{:08x} huge -> {:08x} {:?}",
+                    virt,
+                    entry.into_physical(),
+                    entry.flags(),
+                );
+                continue;
+            }
+
+            kprintln!(
+                "{:08x} table @ {:08x} {:?}",
+                virt,
+                entry.into_physical(),
+                entry.flags(),
+            );
+
+            // print runs of contiguous pages with equal flags as one line
+            let range =
+                Virtual::new(virt)..Virtual::new(virt + (HUGE_FRAME_SIZE - 1));
+            let mut run: Option<(Mapping, usize)> = None;
+            for mapping in self.mappings(range) {
+                run = match run {
+                    Some((first, count)) if first.is_run(count, &mapping) => {
+                        Some((first, count + 1))
+                    }
+                    Some((first, count)) => {
+                        dump_run(first, count);
+                        Some((mapping, 1))
+                    }
+                    None => Some((mapping, 1)),
+                };
+            }
+            if let Some((first, count)) = run {
+                dump_run(first, count);
+            }
+        }
     }
 
     pub fn map(&mut self, virt: Virtual, entry: Entry) -> Option<Entry> {
-        let idx = virt.into_inner() >> 22;
+        let idx = directory_index(virt);
         let old = if self.inner[idx].is_used() {
             Some(self.inner[idx])
         } else {
@@ -29,6 +276,42 @@ impl<'a> Table<'a> {
         old
     }
 
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let user = entry.flags().contains(Flags::USER);
+        let table = self.table_or_create(directory_index(virt), user, frames);
+        let idx = table_index(virt);
+        let old = if table[idx].is_used() {
+            Some(table[idx])
+        } else {
+            None
+        };
+        table[idx] = entry;
+        old
+    }
+
+    // like map_page, but only into a page table that exists already, so no
+    // frames are needed, None if there's no such table
+    pub fn remap_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+    ) -> Option<Option<Entry>> {
+        let table = self.table_mut(directory_index(virt))?;
+        let idx = table_index(virt);
+        let old = if table[idx].is_used() {
+            Some(table[idx])
+        } else {
+            None
+        };
+        table[idx] = entry;
+        Some(old)
+    }
+
     pub fn default_map(
         &mut self,
         virt: Virtual,
@@ -44,13 +327,61 @@ impl<'a> Table<'a> {
         self.map(virt, entry)
     }
 
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let entry = EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .page_size(PageSize::Normal)
+            .build();
+
+        self.map_page(virt, entry, frames)
+    }
+
+    // kernel pages are global, so they stay cached across table switches
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let entry = EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .global()
+            .page_size(PageSize::Normal)
+            .build();
+
+        self.map_page(virt, entry, frames)
+    }
+
+    // unmaps whatever maps virt, a 4 KiB page or a whole huge page
     pub fn unmap(&mut self, virt: Virtual) -> Option<Entry> {
+        if let Some(table) = self.table_mut(directory_index(virt)) {
+            let idx = table_index(virt);
+            let old = table[idx];
+            table[idx] = Entry::empty();
+            return if old.is_used() { Some(old) } else { None };
+        }
+
         self.map(virt, Entry::empty())
     }
 
     pub fn is_used(&self, virt: Virtual) -> bool {
-        let idx = virt.into_inner() >> 22;
-        self.inner[idx].is_used()
+        let idx = directory_index(virt);
+        if self.inner[idx].is_huge() {
+            return true;
+        }
+
+        self.table(idx)
+            .map(|table| table[table_index(virt)].is_used())
+            .unwrap_or(false)
     }
 
     pub unsafe fn reset_cache(&mut self) {
@@ -58,6 +389,92 @@ impl<'a> Table<'a> {
     }
 }
 
+fn dump_run(first: Mapping, count: usize) {
+    kprintln!(
+        "  {:08x}-{:08x} -> {:08x} {:?}",
+        first.virt,
+        first.virt.into_inner() + count * FRAME_SIZE - 1,
+        first.phys,
+        first.flags,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mapping {
+    pub virt: Virtual,
+    pub phys: Physical,
+    pub size: PageSize,
+    pub flags: Flags,
+}
+
+impl Mapping {
+    // whether next directly follows count pages starting at self
+    fn is_run(&self, count: usize, next: &Mapping) -> bool {
+        let offset = count * self.size.bytes();
+        next.flags == self.flags
+            && next.virt == self.virt + offset
+            && next.phys == self.phys + offset
+    }
+}
+
+// iterates over every present page in a range, huge pages are returned once
+// with their aligned address
+pub struct Mappings<'t, 'a: 't> {
+    table: &'t Table<'a>,
+    next: Option<usize>,
+    end: usize,
+}
+
+impl<'t, 'a: 't> Iterator for Mappings<'t, 'a> {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        while let Some(addr) = self.next {
+            if addr >= self.end {
+                break;
+            }
+
+            let virt = Virtual::new(addr);
+            let idx = directory_index(virt);
+            let dir = self.table.inner[idx];
+            let next_dir = (addr & !(HUGE_FRAME_SIZE - 1))
+                .checked_add(HUGE_FRAME_SIZE);
+
+            // the recursive entry maps the page tables, not actual pages
+            let recursive =
+                is_recursive(idx) && self.table.tables == Tables::Recursive;
+            if recursive || !dir.is_used() {
+                self.next = next_dir;
+                continue;
+            }
+
+            if dir.is_huge() {
+                self.next = next_dir;
+                return Some(Mapping {
+                    virt: Virtual::new(addr & !(HUGE_FRAME_SIZE - 1)),
+                    phys: dir.into_physical(),
+                    size: PageSize::Huge,
+                    flags: dir.flags(),
+                });
+            }
+
+            let entry = self.table.table(idx).unwrap()[table_index(virt)];
+            self.next = (addr & !(FRAME_SIZE - 1)).checked_add(FRAME_SIZE);
+            if entry.is_used() {
+                return Some(Mapping {
+                    virt: Virtual::new(addr & !(FRAME_SIZE - 1)),
+                    phys: entry.into_physical(),
+                    size: PageSize::Normal,
+                    flags: entry.flags(),
+                });
+            }
+        }
+
+        self.next = None;
+        None
+    }
+}
+
 impl<'a> Deref for Table<'a> {
     type Target = [Entry];
 
@@ -72,31 +489,118 @@ impl<'a> DerefMut for Table<'a> {
     }
 }
 
+fn recursive_entry(phys: Physical) -> Entry {
+    EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .read_write()
+        .page_size(PageSize::Normal)
+        .build()
+}
+
+// points the recursive entries at the directories, which start at phys, and
+// returns the value for cr3
+#[cfg(not(feature = "pae"))]
+fn set_recursive(table: &mut Table, phys: Physical) -> usize {
+    table[RECURSIVE_IDX] = recursive_entry(phys);
+    phys.into_inner() as usize
+}
+
+#[cfg(feature = "pae")]
+fn set_recursive(table: &mut Table, phys: Physical) -> usize {
+    for dir in 0..DIRECTORIES {
+        table[RECURSIVE_IDX + dir] = recursive_entry(phys + dir * FRAME_SIZE);
+    }
+
+    // the pointer table follows the directories, its entries may only have the
+    // present and cache bits set
+    let pdpt = unsafe {
+        let ptr = table.as_mut_ptr().offset(DIRECTORY_ENTRIES as isize);
+        slice::from_raw_parts_mut(ptr, DIRECTORIES)
+    };
+    for (dir, entry) in pdpt.iter_mut().enumerate() {
+        *entry = EntryBuilder::new()
+            .addr(phys + dir * FRAME_SIZE)
+            .present()
+            .page_size(PageSize::Normal)
+            .build();
+    }
+
+    phys.into_inner() as usize + DIRECTORIES * FRAME_SIZE
+}
+
 pub struct ActiveTable<'a> {
     inner: Table<'a>,
 }
 
 impl<'a> ActiveTable<'a> {
-    pub fn map(&mut self, virt: Virtual, entry: Entry) -> Option<Entry> {
-        self.inner.map(virt, entry)
+    pub fn map(&mut self, virt: Virtual, entry: Entry) -> Flush {
+        Flush::new(virt, self.inner.map(virt, entry))
+    }
+
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.map_page(virt, entry, frames))
+    }
+
+    pub fn remap_page(&mut self, virt: Virtual, entry: Entry) -> Option<Flush> {
+        self.inner
+            .remap_page(virt, entry)
+            .map(|old| Flush::new(virt, old))
     }
 
     pub fn default_map(
         &mut self,
         virt: Virtual,
         phys: Physical,
-    ) -> Option<Entry> {
-        self.inner.default_map(virt, phys)
+    ) -> Flush {
+        Flush::new(virt, self.inner.default_map(virt, phys))
     }
 
-    pub fn unmap(&mut self, virt: Virtual) -> Option<Entry> {
-        self.inner.unmap(virt)
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.default_map_page(virt, phys, frames))
+    }
+
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.global_map_page(virt, phys, frames))
+    }
+
+    pub fn unmap(&mut self, virt: Virtual) -> Flush {
+        Flush::new(virt, self.inner.unmap(virt))
     }
 
     pub fn is_used(&self, virt: Virtual) -> bool {
-        self.inner.is_used(virt)
+        // the recursive entries map the page tables themselves
+        is_recursive(directory_index(virt)) || self.inner.is_used(virt)
+    }
+
+    pub fn translate(&self, virt: Virtual) -> Option<(Physical, Flags)> {
+        self.inner.translate(virt)
+    }
+
+    pub fn mappings<'t>(&'t self, range: Range<Virtual>) -> Mappings<'t, 'a> {
+        self.inner.mappings(range)
+    }
+
+    pub fn dump(&self) {
+        self.inner.dump()
     }
 
+    // flushes every non-global translation, prefer flushing single pages
     pub fn reset_cache(&mut self) {
         unsafe {
             self.inner.reset_cache();
@@ -109,25 +613,53 @@ pub struct InactiveTable<'a> {
 }
 
 impl<'a> InactiveTable<'a> {
+    // the page tables are expected at their physical address, which only holds
+    // before paging is enabled
     pub fn new(inner: &'a mut [Entry]) -> InactiveTable<'a> {
+        InactiveTable::with_offset(inner, 0)
+    }
+
+    // the page tables are expected at their physical address plus offset
+    pub fn with_offset(
+        inner: &'a mut [Entry],
+        offset: usize,
+    ) -> InactiveTable<'a> {
         assert!(
-            inner.len() == 1024,
-            "page directory must have 1024 entries, is {}",
+            inner.len() == DIRECTORY_ENTRIES,
+            "page directory must have {} entries, is {}",
+            DIRECTORY_ENTRIES,
             inner.len()
         );
         InactiveTable {
-            inner: unsafe { Table::new(inner) },
+            inner: unsafe { Table::new(inner, Tables::Offset(offset)) },
         }
     }
 
+    fn physical(&self) -> Physical {
+        let offset = match self.inner.tables {
+            Tables::Offset(offset) => offset,
+            Tables::Recursive => unreachable!(),
+        };
+        Physical::new((self.inner.as_ptr() as usize - offset) as PhysAddr)
+    }
+
     pub fn into_physical(self) -> Physical {
-        Physical::new(self.inner.as_ptr() as usize)
+        self.physical()
     }
 
     pub fn map(&mut self, virt: Virtual, entry: Entry) -> Option<Entry> {
         self.inner.map(virt, entry)
     }
 
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.map_page(virt, entry, frames)
+    }
+
     pub fn default_map(
         &mut self,
         virt: Virtual,
@@ -136,6 +668,24 @@ impl<'a> InactiveTable<'a> {
         self.inner.default_map(virt, phys)
     }
 
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.default_map_page(virt, phys, frames)
+    }
+
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.global_map_page(virt, phys, frames)
+    }
+
     pub fn unmap(&mut self, virt: Virtual) -> Option<Entry> {
         self.inner.unmap(virt)
     }
@@ -146,58 +696,66 @@ impl<'a> InactiveTable<'a> {
 
     #[must_use]
     pub unsafe fn load(mut self) -> ActiveTable<'a> {
-        let phys = Physical::new(self.inner.as_ptr() as usize);
-        let offset = phys.into_inner() & (FRAME_SIZE - 1);
-        self.default_map(Virtual::new(0xffc00000), phys & !(FRAME_SIZE - 1));
+        let phys = self.physical();
+        let root = set_recursive(&mut self.inner, phys);
 
-        cr3_write(phys.into_inner());
+        // always reloaded, even if cr3 already holds root, the tlb may still
+        // have translations through the recursive entries from before
+        cr3_write(root);
 
-        let inner = Table::new(slice::from_raw_parts_mut(
-            (0xffc00000 + offset) as *mut _,
-            1024,
-        ));
+        let inner = Table::new(
+            slice::from_raw_parts_mut(DIRECTORY as *mut _, DIRECTORY_ENTRIES),
+            Tables::Recursive,
+        );
         ActiveTable { inner }
     }
+}
 
-    // addr is the virtual address to which the current active table will be
-    // mapped
-    #[must_use]
-    pub fn switch<'b>(
-        mut self,
-        active: ActiveTable<'b>,
-        addr: Virtual,
-    ) -> (ActiveTable<'a>, InactiveTable<'b>) {
-        let old_phys =
-            Physical::new(active.inner[0x3ff].into_physical().into_inner());
-        let old_offset = old_phys.into_inner() & (FRAME_SIZE - 1);
-
-        // self virtual idx
-        let idx = self.inner.as_ptr() as usize >> 22;
-        // self physical address
-        let new_phys = active.inner[idx].into_physical();
-        let new_offset = new_phys.into_inner() & (FRAME_SIZE - 1);
-        self.default_map(
-            Virtual::new(0xffc00000),
-            new_phys & !(FRAME_SIZE - 1),
-        );
-        self.default_map(addr, old_phys & !(FRAME_SIZE - 1));
+// what a new root holds at idx, where the pointer table's entries follow the
+// directory's
+fn root_entry(root: Physical, idx: usize) -> Entry {
+    if idx < DIRECTORY_ENTRIES {
+        if is_recursive(idx) {
+            recursive_entry(root + (idx - RECURSIVE_IDX) * FRAME_SIZE)
+        } else {
+            Entry::empty()
+        }
+    } else if idx - DIRECTORY_ENTRIES < DIRECTORIES {
+        EntryBuilder::new()
+            .addr(root + (idx - DIRECTORY_ENTRIES) * FRAME_SIZE)
+            .present()
+            .page_size(PageSize::Normal)
+            .build()
+    } else {
+        Entry::empty()
+    }
+}
 
-        let new_active = unsafe {
-            cr3_write(new_phys.into_inner());
+// clears the ROOT_FRAMES contiguous frames at root for a new hierarchy, except
+// for the recursive entries and, with pae, the pointer table
+pub fn init_root(root: Physical) {
+    for frame in 0..ROOT_FRAMES {
+        let mut slot = kmap::kmap(root + frame * FRAME_SIZE)
+            .unwrap_or_else(|| panic!("no kmap slot for a new directory"));
+        let first = frame * (FRAME_SIZE / ENTRY_SIZE);
+        for (idx, entry) in slot.entries().iter_mut().enumerate() {
+            *entry = root_entry(root, first + idx);
+        }
+    }
+}
 
-            let inner = Table::new(slice::from_raw_parts_mut(
-                (0xffc00000 + new_offset) as *mut _,
-                1024,
-            ));
-            ActiveTable { inner }
-        };
-        let new_inactive = unsafe {
-            let inner = Table::new(slice::from_raw_parts_mut(
-                (addr.into_inner() + old_offset) as *mut _,
-                1024,
-            ));
-            InactiveTable { inner }
-        };
-        (new_active, new_inactive)
+// cr3 holds the pointer table with pae, which follows the directories
+//
+// cr3 is 32 bits wide, so roots lie below 4 GiB
+pub fn active_root() -> Physical {
+    let value = unsafe { cr3() } - (ROOT_FRAMES - 1) * FRAME_SIZE;
+    Physical::new(value as PhysAddr)
+}
+
+// the root must have been set up by init_root
+pub unsafe fn load_root(root: Physical) {
+    let value = root.into_inner() as usize + (ROOT_FRAMES - 1) * FRAME_SIZE;
+    if cr3() != value {
+        cr3_write(value);
     }
 }
@@ -0,0 +1,131 @@
+// the tlb caches translations until they're invalidated, changes to a present
+// entry of the active table only take effect once the page has been flushed
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use x86::shared::control_regs::{cr4, cr4_write, CR4_ENABLE_GLOBAL_PAGES};
+use x86::shared::tlb;
+
+use raw_cpuid::CpuId;
+
+use arch::cpuid;
+use arch::paging::addr::Virtual;
+use arch::paging::table::{Entry, Flags};
+
+// past this many pages one full flush is cheaper than an invlpg per page
+const BATCH_PAGES: usize = 32;
+
+static GLOBAL_PAGES: AtomicBool = ATOMIC_BOOL_INIT;
+
+// lets global pages survive cr3 reloads if the cpu supports it, returns
+// whether it does
+pub unsafe fn enable_global_pages() -> bool {
+    let supported = cpuid::available()
+        && CpuId::new()
+            .get_feature_info()
+            .map_or(false, |info| info.has_pge());
+
+    if supported {
+        cr4_write(cr4() | CR4_ENABLE_GLOBAL_PAGES);
+        GLOBAL_PAGES.store(true, Ordering::SeqCst);
+    }
+    supported
+}
+
+pub unsafe fn flush(virt: Virtual) {
+    tlb::flush(virt.into_inner());
+}
+
+// flushes every translation except global ones
+pub unsafe fn flush_all() {
+    tlb::flush_all();
+}
+
+// flushes every translation, global ones included
+pub unsafe fn flush_global() {
+    if GLOBAL_PAGES.load(Ordering::Relaxed) {
+        let cr4 = cr4();
+        cr4_write(cr4 & !CR4_ENABLE_GLOBAL_PAGES);
+        cr4_write(cr4);
+    } else {
+        tlb::flush_all();
+    }
+}
+
+// a change to the active table that isn't visible until it's flushed, it
+// carries the entry that was replaced
+#[must_use = "the tlb may still hold the old mapping"]
+pub struct Flush {
+    virt: Virtual,
+    old: Option<Entry>,
+}
+
+impl Flush {
+    pub(crate) fn new(virt: Virtual, old: Option<Entry>) -> Flush {
+        Flush { virt, old }
+    }
+
+    pub fn old(&self) -> Option<Entry> {
+        self.old
+    }
+
+    // non-present entries are never cached, so replacing one needs no flush
+    pub fn flush(self) -> Option<Entry> {
+        if self.old.is_some() {
+            unsafe { flush(self.virt) }
+        }
+        self.old
+    }
+
+    // the caller flushes some other way, e.g. by switching tables
+    pub fn ignore(self) -> Option<Entry> {
+        self.old
+    }
+}
+
+// collects flushes and does them at once, per page for a few pages and with a
+// full flush for many
+#[must_use = "the tlb may still hold the old mappings"]
+pub struct Batch {
+    pages: [Virtual; BATCH_PAGES],
+    len: usize,
+    full: bool,
+    global: bool,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch {
+            pages: [Virtual::new(0); BATCH_PAGES],
+            len: 0,
+            full: false,
+            global: false,
+        }
+    }
+
+    pub fn add(&mut self, flush: Flush) -> Option<Entry> {
+        if let Some(entry) = flush.old {
+            if self.len < BATCH_PAGES {
+                self.pages[self.len] = flush.virt;
+                self.len += 1;
+            } else {
+                self.full = true;
+            }
+            self.global |= entry.flags().contains(Flags::GLOBAL);
+        }
+        flush.old
+    }
+
+    pub fn flush(self) {
+        unsafe {
+            if !self.full {
+                for &virt in &self.pages[..self.len] {
+                    flush(virt);
+                }
+            } else if self.global {
+                flush_global();
+            } else {
+                flush_all();
+            }
+        }
+    }
+}
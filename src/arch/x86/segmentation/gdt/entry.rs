@@ -1,4 +1,7 @@
 use core::fmt;
+use core::mem;
+
+use super::tss::Tss;
 
 #[repr(C, packed)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -24,6 +27,26 @@ impl Entry {
             base_3: 0,
         }
     }
+
+    pub fn base(&self) -> usize {
+        (self.base_3 as usize) << 24 | (self.base_2 as usize) << 16
+            | self.base_1 as usize
+    }
+
+    // long mode system descriptors take two entries, the second one holds the
+    // upper half of the base
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) fn upper_base(base: usize) -> Entry {
+        let upper = (base >> 32) as u32;
+        Entry {
+            limit_1: (upper & 0xffff) as u16,
+            base_1: (upper >> 16) as u16,
+            base_2: 0,
+            access: 0,
+            limit_2_flags: 0,
+            base_3: 0,
+        }
+    }
 }
 
 impl fmt::Debug for Entry {
@@ -68,6 +91,7 @@ bitflags! {
     pub struct Flags: u8 {
         const PAGE_GR = 0b10000000;
         const SIZE_32 = 0b01000000;
+        const LONG = 0b00100000; // 64-bit code, size has to be 16
     }
 }
 
@@ -83,6 +107,7 @@ bitflags! {
         const DIR = 0b00000100; // no support for DIR=1
         const RW = 0b00000010;
         const ACCESS = 0b00000001;
+        const TSS = 0b00001001; // an available tss, ONE has to be zero
     }
 }
 
@@ -189,10 +214,12 @@ impl EntryBuilder {
     }
 
     pub fn size(mut self, size: u8) -> EntryBuilder {
+        let flags = self.flags.unwrap_or_default() & !Flags::LONG;
         self.flags = Some(match size {
-            16 => self.flags.unwrap_or_default() & !Flags::SIZE_32,
-            32 => self.flags.unwrap_or_default() | Flags::SIZE_32,
-            _ => panic!("gdt::Entry size must be 16 or 32"),
+            16 => flags & !Flags::SIZE_32,
+            32 => flags | Flags::SIZE_32,
+            64 => flags & !Flags::SIZE_32 | Flags::LONG,
+            _ => panic!("gdt::Entry size must be 16, 32 or 64"),
         });
         self
     }
@@ -220,4 +247,13 @@ impl EntryBuilder {
         self.access = Some(self.access.unwrap_or_default() | Access::RW);
         self
     }
+
+    // a descriptor for tss, the caller decides on the ring
+    pub fn tss(mut self, tss: &Tss) -> EntryBuilder {
+        self.base = Some(tss as *const Tss as usize as u32);
+        self.limit = Some(mem::size_of::<Tss>() as u32 - 1);
+        self.flags = Some(Flags::default());
+        self.access = Some(Access::PRESENT | Access::TSS);
+        self
+    }
 }
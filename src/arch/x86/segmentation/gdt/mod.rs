@@ -1,9 +1,13 @@
 use core::mem;
 use core::fmt;
+use core::slice;
 
 pub mod entry;
 pub use self::entry::*;
 
+pub mod tss;
+pub use self::tss::Tss;
+
 const ENTRY_SIZE: usize = 8;
 
 #[repr(C, packed)]
@@ -12,6 +16,17 @@ pub struct Gdtr {
     base: u32,
 }
 
+impl Gdtr {
+    // the gdtr the cpu has loaded
+    pub fn current() -> Gdtr {
+        let mut gdtr = Gdtr { limit: 0, base: 0 };
+        unsafe {
+            asm!("sgdtl   ($0)" : : "r"(&mut gdtr) : "memory" : "volatile");
+        }
+        gdtr
+    }
+}
+
 impl fmt::LowerHex for Gdtr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         unsafe { write!(f, "{:04x}{:08x}", self.limit, self.base) }
@@ -27,6 +42,14 @@ impl<'a> Gdt<'a> {
         Gdt { inner }
     }
 
+    // the gdt the cpu has loaded, nothing else may use its table meanwhile
+    pub unsafe fn current() -> Gdt<'static> {
+        let gdtr = Gdtr::current();
+        let len = (gdtr.limit as usize + 1) / mem::size_of::<Entry>();
+        let base = gdtr.base as usize as *mut _;
+        Gdt::with_table(slice::from_raw_parts_mut(base, len))
+    }
+
     pub fn gdtr(&self) -> Gdtr {
         Gdtr {
             limit: (self.inner.len() * mem::size_of::<Entry>() - 1) as u16,
@@ -41,4 +64,14 @@ impl<'a> Gdt<'a> {
         );
         self.inner[num as usize / ENTRY_SIZE] = entry;
     }
+
+    pub fn entry(&self, num: u16) -> Entry {
+        self.inner[num as usize / ENTRY_SIZE]
+    }
+
+    // the tss must stay where it is for as long as the gdt is loaded
+    pub fn new_tss(&mut self, num: u16, tss: &'static Tss) {
+        let entry = EntryBuilder::new().tss(tss).ring(RingLevel::Ring0).build();
+        self.new_entry(num, entry);
+    }
 }
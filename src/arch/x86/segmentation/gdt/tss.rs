@@ -0,0 +1,126 @@
+// a task state segment, on a task switch the cpu saves the running task's
+// state into the current tss and loads the new task's state from its tss
+//
+// the kernel only switches tasks to handle double faults on a stack of their
+// own, the running task's tss also holds the stack for entering ring 0
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct Tss {
+    pub link: u16,
+    _reserved_1: u16,
+    pub esp0: u32,
+    pub ss0: u16,
+    _reserved_2: u16,
+    pub esp1: u32,
+    pub ss1: u16,
+    _reserved_3: u16,
+    pub esp2: u32,
+    pub ss2: u16,
+    _reserved_4: u16,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u16,
+    _reserved_5: u16,
+    pub cs: u16,
+    _reserved_6: u16,
+    pub ss: u16,
+    _reserved_7: u16,
+    pub ds: u16,
+    _reserved_8: u16,
+    pub fs: u16,
+    _reserved_9: u16,
+    pub gs: u16,
+    _reserved_10: u16,
+    pub ldt: u16,
+    _reserved_11: u16,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+// the io bitmap would follow the tss, pointing past the limit means there is
+// none
+const SIZE: u16 = 104;
+
+// only the reserved bit is set, interrupts are off
+const EFLAGS: u32 = 0x2;
+
+impl Tss {
+    pub fn new() -> Tss {
+        Tss {
+            link: 0,
+            _reserved_1: 0,
+            esp0: 0,
+            ss0: 0,
+            _reserved_2: 0,
+            esp1: 0,
+            ss1: 0,
+            _reserved_3: 0,
+            esp2: 0,
+            ss2: 0,
+            _reserved_4: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            _reserved_5: 0,
+            cs: 0,
+            _reserved_6: 0,
+            ss: 0,
+            _reserved_7: 0,
+            ds: 0,
+            _reserved_8: 0,
+            fs: 0,
+            _reserved_9: 0,
+            gs: 0,
+            _reserved_10: 0,
+            ldt: 0,
+            _reserved_11: 0,
+            trap: 0,
+            iomap_base: SIZE,
+        }
+    }
+
+    // the stack the cpu switches to when an interrupt leaves an outer ring
+    pub fn set_kernel_stack(&mut self, ss: u16, esp: usize) {
+        self.ss0 = ss;
+        self.esp0 = esp as u32;
+    }
+
+    // where a task switch to this tss starts, with interrupts off
+    pub fn set_entry(
+        &mut self,
+        eip: usize,
+        esp: usize,
+        code: u16,
+        data: u16,
+        cr3: usize,
+    ) {
+        self.eip = eip as u32;
+        self.esp = esp as u32;
+        self.eflags = EFLAGS;
+        self.cr3 = cr3 as u32;
+        self.cs = code;
+        self.ds = data;
+        self.es = data;
+        self.fs = data;
+        self.gs = data;
+        self.ss = data;
+    }
+}
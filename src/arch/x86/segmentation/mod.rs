@@ -17,3 +17,8 @@ pub unsafe fn reload_segments(code: u16, data: u16) {
     load_gs(SegmentSelector::from_raw(data));
     load_ss(SegmentSelector::from_raw(data));
 }
+
+// loads the task register, the descriptor at selector must be an available tss
+pub unsafe fn ltr(selector: u16) {
+    asm!("ltr     $0" : : "r"(selector) : "memory" : "volatile");
+}
@@ -0,0 +1,171 @@
+use core::slice;
+
+use x86::shared::msr::{rdmsr, IA32_EFER};
+use x86::shared::segmentation::{load_fs, SegmentSelector};
+
+use arch::paging::table;
+use arch::segmentation::gdt::{self, Gdt};
+
+// where the trampoline is copied to, the startup ipi takes its page number
+pub const TRAMPOLINE: usize = 0x8000;
+
+// the data segment of the per-cpu block, every gdt has it at the same place
+const PERCPU_SELECTOR: u16 = 0x28;
+
+// an application processor starts in real mode at TRAMPOLINE, the trampoline
+// loads a flat gdt, enables protected mode and paging like the bootstrap
+// processor has them, and calls the entry with arg on the stack given in
+// trampoline_args
+//
+// it's copied to TRAMPOLINE before it runs, so every address in it is
+// relative to that
+global_asm!(
+    r#"
+.set trampoline_base, 0x8000
+
+.macro FAR_JUMP selector, label
+        ljmpl   $\selector, $(\label - trampoline_start + trampoline_base)
+.endm
+
+.section .rodata
+.code16
+.global trampoline_start
+trampoline_start:
+        cli
+        cld
+        xorw    %ax, %ax
+        movw    %ax, %ds
+
+        lgdtl   trampoline_gdtr - trampoline_start + trampoline_base
+
+        movl    %cr0, %eax
+        orl     $1, %eax
+        movl    %eax, %cr0
+
+        FAR_JUMP 0x08, trampoline.protected
+
+.code32
+trampoline.protected:
+        movw    $0x10, %ax
+        movw    %ax, %ds
+        movw    %ax, %es
+        movw    %ax, %ss
+
+        movl    trampoline_cr4 - trampoline_start + trampoline_base, %eax
+        movl    %eax, %cr4
+
+        movl    trampoline_efer - trampoline_start + trampoline_base, %eax
+        testl   %eax, %eax
+        jz      trampoline.no_efer
+        movl    $0xc0000080, %ecx
+        xorl    %edx, %edx
+        wrmsr
+trampoline.no_efer:
+
+        movl    trampoline_cr3 - trampoline_start + trampoline_base, %eax
+        movl    %eax, %cr3
+
+        movl    %cr0, %eax
+        orl     $0x80010000, %eax
+        movl    %eax, %cr0
+
+        movl    trampoline_stack - trampoline_start + trampoline_base, %esp
+        xorl    %ebp, %ebp
+        pushl   trampoline_arg - trampoline_start + trampoline_base
+        movl    trampoline_entry - trampoline_start + trampoline_base, %eax
+        call    *%eax
+
+.align 8
+trampoline_gdt:
+.quad   0
+.quad   0x00cf9a000000ffff
+.quad   0x00cf92000000ffff
+trampoline_gdtr:
+.word   trampoline_gdtr - trampoline_gdt - 1
+.long   trampoline_gdt - trampoline_start + trampoline_base
+
+.align 4
+.global trampoline_args
+trampoline_args:
+trampoline_cr3:
+.long   0
+trampoline_cr4:
+.long   0
+trampoline_efer:
+.long   0
+trampoline_stack:
+.long   0
+trampoline_entry:
+.long   0
+trampoline_arg:
+.long   0
+.global trampoline_end
+trampoline_end:
+"#
+);
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_args: u8;
+    static trampoline_end: u8;
+}
+
+// the trampoline as it's copied to TRAMPOLINE
+pub fn trampoline() -> &'static [u8] {
+    unsafe {
+        let start = &trampoline_start as *const u8;
+        let len = &trampoline_end as *const u8 as usize - start as usize;
+        slice::from_raw_parts(start, len)
+    }
+}
+
+// where in the trampoline its arguments are
+pub fn args_offset() -> usize {
+    unsafe {
+        &trampoline_args as *const u8 as usize
+            - &trampoline_start as *const u8 as usize
+    }
+}
+
+// what the trampoline writes to efer, 0 leaves it alone, a cpu without
+// no-execute may not have efer at all
+pub fn efer() -> usize {
+    if table::no_execute_enabled() {
+        unsafe { rdmsr(IA32_EFER) as usize }
+    } else {
+        0
+    }
+}
+
+// points fs at the per-cpu block at addr through a data segment in the gdt
+// this cpu has loaded
+pub unsafe fn set_percpu(addr: usize, size: usize) {
+    let mut gdt = Gdt::current();
+    gdt.new_entry(
+        PERCPU_SELECTOR,
+        gdt::EntryBuilder::new()
+            .base(addr)
+            .limit(size - 1)
+            .granularity(gdt::Granularity::Bit)
+            .size(32)
+            .present()
+            .ring(gdt::RingLevel::Ring0)
+            .read_write()
+            .build(),
+    );
+    load_fs(SegmentSelector::from_raw(PERCPU_SELECTOR));
+}
+
+// whether set_percpu ran on this cpu, fs holds another selector until then
+pub fn has_percpu() -> bool {
+    let fs: u16;
+    unsafe { asm!("movw    %fs, $0" : "=r"(fs) : : : "volatile") };
+    fs == PERCPU_SELECTOR
+}
+
+// the per-cpu block starts with its own address, set_percpu must have run
+pub unsafe fn percpu() -> usize {
+    let addr: usize;
+    asm!("movl    %fs:0, $0" : "=r"(addr) : : : "volatile");
+    addr
+}
@@ -0,0 +1,4 @@
+// every cpu that supports long mode supports cpuid
+pub fn available() -> bool {
+    true
+}
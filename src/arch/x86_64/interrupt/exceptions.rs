@@ -0,0 +1,446 @@
+use core::fmt;
+
+use spin::Once;
+
+use x86::shared::control_regs::{cr0, cr2, cr3, cr4};
+
+use arch::kernel;
+use arch::paging::addr::Virtual;
+use mem::lazy;
+use mem::space;
+use mem::stack;
+
+bitflags! {
+    pub struct PageFaultError: usize {
+        const PRESENT = 0b00001; // protection violation, not a missing page
+        const WRITE = 0b00010;
+        const USER = 0b00100;
+        const RESERVED = 0b01000;
+        const INSTRUCTION = 0b10000;
+    }
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cause = if self.contains(PageFaultError::PRESENT) {
+            "protection violation"
+        } else {
+            "page not present"
+        };
+        let access = if self.contains(PageFaultError::INSTRUCTION) {
+            "instruction fetch"
+        } else if self.contains(PageFaultError::WRITE) {
+            "write"
+        } else {
+            "read"
+        };
+        let mode = if self.contains(PageFaultError::USER) {
+            "user"
+        } else {
+            "kernel"
+        };
+
+        write!(f, "{} on {} in {} mode", cause, access, mode)?;
+        if self.contains(PageFaultError::RESERVED) {
+            write!(f, ", reserved bit set")?;
+        }
+        Ok(())
+    }
+}
+
+// the error code of #TS, #NP, #SS and #GP, it names the selector that caused
+// the fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SelectorError(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+impl SelectorError {
+    pub fn new(code: usize) -> SelectorError {
+        SelectorError(code & 0xffff)
+    }
+
+    // the fault happened while delivering an interrupt from outside
+    pub fn external(&self) -> bool {
+        self.0 & 0b001 != 0
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        if self.0 & 0b010 != 0 {
+            DescriptorTable::Idt
+        } else if self.0 & 0b100 != 0 {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 >> 3
+    }
+
+    // the selector as it would be loaded, without the requested privilege
+    // level
+    pub fn selector(&self) -> u16 {
+        (self.0 & !0b011) as u16
+    }
+}
+
+impl fmt::Display for DescriptorTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DescriptorTable::Gdt => write!(f, "gdt"),
+            DescriptorTable::Idt => write!(f, "idt"),
+            DescriptorTable::Ldt => write!(f, "ldt"),
+        }
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "no selector");
+        }
+        match self.table() {
+            DescriptorTable::Idt => {
+                write!(f, "idt vector {:02x}", self.index())?
+            }
+            table => write!(f, "{} selector {:04x}", table, self.selector())?,
+        }
+        if self.external() {
+            write!(f, ", external event")?;
+        }
+        Ok(())
+    }
+}
+
+// the state the entry stubs save, in the order they push it
+#[repr(C)]
+pub struct Registers {
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub r11: usize,
+    pub r10: usize,
+    pub r9: usize,
+    pub r8: usize,
+    pub rbp: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rdx: usize,
+    pub rcx: usize,
+    pub rbx: usize,
+    pub rax: usize,
+    pub vector: usize,
+    // 0 for exceptions without an error code
+    pub code: usize,
+    pub rip: usize,
+    pub cs: usize,
+    pub rflags: usize,
+    // long mode always pushes the stack pointer
+    pub rsp: usize,
+    pub ss: usize,
+}
+
+impl Registers {
+    pub fn from_user(&self) -> bool {
+        self.cs & 0b11 != 0
+    }
+}
+
+// a register dump, the control registers are read when it's printed
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (cr0, cr2, cr3, cr4) =
+            unsafe { (cr0().bits(), cr2(), cr3(), cr4().bits()) };
+        writeln!(
+            f,
+            "rax: {:016x} rbx: {:016x} rcx: {:016x}",
+            self.rax,
+            self.rbx,
+            self.rcx,
+        )?;
+        writeln!(
+            f,
+            "rdx: {:016x} rsi: {:016x} rdi: {:016x}",
+            self.rdx,
+            self.rsi,
+            self.rdi,
+        )?;
+        writeln!(
+            f,
+            "rbp: {:016x} rsp: {:016x} r8:  {:016x}",
+            self.rbp,
+            self.rsp,
+            self.r8,
+        )?;
+        writeln!(
+            f,
+            "r9:  {:016x} r10: {:016x} r11: {:016x}",
+            self.r9,
+            self.r10,
+            self.r11,
+        )?;
+        writeln!(
+            f,
+            "r12: {:016x} r13: {:016x} r14: {:016x}",
+            self.r12,
+            self.r13,
+            self.r14,
+        )?;
+        writeln!(
+            f,
+            "r15: {:016x} rip: {:016x} rflags: {:08x}",
+            self.r15,
+            self.rip,
+            self.rflags,
+        )?;
+        writeln!(f, "cs: {:04x} ss: {:04x}", self.cs, self.ss)?;
+        writeln!(f, "cr0: {:016x} cr2: {:016x}", cr0, cr2)?;
+        write!(f, "cr3: {:016x} cr4: {:016x}", cr3, cr4)
+    }
+}
+
+pub fn name(vector: usize) -> &'static str {
+    match vector {
+        0x0 => "divide-by-zero error",
+        0x1 => "debug",
+        0x2 => "non-maskable interrupt",
+        0x3 => "breakpoint",
+        0x4 => "overflow",
+        0x5 => "bound range exceeded",
+        0x6 => "invalid opcode",
+        0x7 => "device not available",
+        0x8 => "double fault",
+        0xa => "invalid tss",
+        0xb => "segment not present",
+        0xc => "stack-segment fault",
+        0xd => "general protection fault",
+        0xe => "page fault",
+        0x10 => "x87",
+        0x11 => "alignment check",
+        0x12 => "machine check",
+        0x13 => "simd floating-point exception",
+        0x14 => "virtualization exception",
+        0x1e => "security exception",
+        _ => "reserved exception",
+    }
+}
+
+// decides what happens to the process a user mode exception came from, the
+// registers are restored from regs when it returns
+pub type UserHandler = fn(&mut Registers);
+
+static USER_HANDLER: Once<UserHandler> = Once::new();
+
+// without a user handler, user mode exceptions panic like kernel mode ones
+pub fn set_user_handler(handler: UserHandler) {
+    USER_HANDLER.call_once(|| handler);
+}
+
+// the entry stubs push a zero for exceptions without an error code, so every
+// exception reaches exception with the same frame
+//
+// the cpu aligns the stack before pushing its frame, which leaves it aligned
+// again after the 17 pushes below
+global_asm!(
+    r#"
+.macro ISR name, vector, code=0
+.global isr_\name
+isr_\name:
+.if \code == 0
+        push    $0
+.endif
+        push    $\vector
+        push    %rax
+        push    %rbx
+        push    %rcx
+        push    %rdx
+        push    %rsi
+        push    %rdi
+        push    %rbp
+        push    %r8
+        push    %r9
+        push    %r10
+        push    %r11
+        push    %r12
+        push    %r13
+        push    %r14
+        push    %r15
+        mov     %rsp, %rdi
+        cld
+        call    exception
+        pop     %r15
+        pop     %r14
+        pop     %r13
+        pop     %r12
+        pop     %r11
+        pop     %r10
+        pop     %r9
+        pop     %r8
+        pop     %rbp
+        pop     %rdi
+        pop     %rsi
+        pop     %rdx
+        pop     %rcx
+        pop     %rbx
+        pop     %rax
+        add     $16, %rsp
+        iretq
+.endm
+
+ISR de, 0x0
+ISR db, 0x1
+ISR ni, 0x2
+ISR bp, 0x3
+ISR of, 0x4
+ISR br, 0x5
+ISR ud, 0x6
+ISR nm, 0x7
+ISR df, 0x8, 1
+ISR ts, 0xa, 1
+ISR np, 0xb, 1
+ISR ss, 0xc, 1
+ISR gp, 0xd, 1
+ISR pf, 0xe, 1
+ISR mf, 0x10
+ISR ac, 0x11, 1
+ISR mc, 0x12
+ISR xm, 0x13
+ISR ve, 0x14
+ISR sx, 0x1e, 1
+"#
+);
+
+extern "C" {
+    #[link_name = "isr_de"]
+    pub fn de(); // 0x0
+    #[link_name = "isr_db"]
+    pub fn db(); // 0x1
+    #[link_name = "isr_ni"]
+    pub fn ni(); // 0x2
+    #[link_name = "isr_bp"]
+    pub fn bp(); // 0x3
+    #[link_name = "isr_of"]
+    pub fn of(); // 0x4
+    #[link_name = "isr_br"]
+    pub fn br(); // 0x5
+    #[link_name = "isr_ud"]
+    pub fn ud(); // 0x6
+    #[link_name = "isr_nm"]
+    pub fn nm(); // 0x7
+    #[link_name = "isr_df"]
+    pub fn df(); // 0x8
+    #[link_name = "isr_ts"]
+    pub fn ts(); // 0xA
+    #[link_name = "isr_np"]
+    pub fn np(); // 0xB
+    #[link_name = "isr_ss"]
+    pub fn ss(); // 0xC
+    #[link_name = "isr_gp"]
+    pub fn gp(); // 0xD
+    #[link_name = "isr_pf"]
+    pub fn pf(); // 0xE
+    #[link_name = "isr_mf"]
+    pub fn mf(); // 0x10
+    #[link_name = "isr_ac"]
+    pub fn ac(); // 0x11
+    #[link_name = "isr_mc"]
+    pub fn mc(); // 0x12
+    #[link_name = "isr_xm"]
+    pub fn xm(); // 0x13
+    #[link_name = "isr_ve"]
+    pub fn ve(); // 0x14
+    #[link_name = "isr_sx"]
+    pub fn sx(); // 0x1E
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn exception(regs: &mut Registers) {
+    match regs.vector {
+        0xa | 0xb | 0xc | 0xd => {
+            let error = SelectorError::new(regs.code);
+            unhandled(regs, format_args!(": {}", error))
+        }
+        0x8 => double_fault(regs),
+        0xe => page_fault(regs),
+        _ => unhandled(regs, format_args!("")),
+    }
+}
+
+// user mode exceptions go to the user handler, everything else is a kernel
+// bug
+fn unhandled(regs: &mut Registers, detail: fmt::Arguments) {
+    if regs.from_user() {
+        if let Some(handler) = USER_HANDLER.try() {
+            return handler(regs);
+        }
+    }
+    panic!("{} ({:#x}){}\n{}", name(regs.vector), regs.vector, detail, regs);
+}
+
+// runs on an interrupt stack of its own, see kernel::init_gdt, so it works
+// even when the fault came from an overflow
+unsafe fn double_fault(regs: &mut Registers) {
+    let addr = Virtual::new(cr2());
+    if let Some(owner) = stack::overflowed(addr) {
+        panic!(
+            "kernel stack overflow in {} at {:016x}\n{}",
+            owner,
+            addr,
+            regs,
+        );
+    }
+    panic!("double fault (0x8)\n{}", regs);
+}
+
+unsafe fn page_fault(regs: &mut Registers) {
+    let addr = Virtual::new(cr2());
+    let error = PageFaultError::from_bits_truncate(regs.code);
+
+    // only missing pages can be backed lazily
+    if !error.contains(PageFaultError::PRESENT) && lazy::resolve(addr, error) {
+        return;
+    }
+
+    // only writes to present pages can hit a copy-on-write page
+    if error.contains(PageFaultError::PRESENT | PageFaultError::WRITE)
+        && space::resolve(addr, error)
+    {
+        return;
+    }
+
+    if let Some(owner) = stack::overflowed(addr) {
+        panic!(
+            "kernel stack overflow in {} at {:016x}\n{}",
+            owner,
+            addr,
+            regs,
+        );
+    }
+
+    let mapping =
+        kernel::try_page_table().and_then(|table| table.translate(addr));
+    match mapping {
+        Some((phys, flags)) => unhandled(
+            regs,
+            format_args!(
+                " at {:016x}: {}, mapped to {:08x} with {:?}",
+                addr,
+                error,
+                phys,
+                flags,
+            ),
+        ),
+        None => unhandled(
+            regs,
+            format_args!(" at {:016x}: {}, not mapped", addr, error),
+        ),
+    }
+}
@@ -0,0 +1,77 @@
+use core::mem;
+use core::fmt;
+
+pub mod entry;
+pub use self::entry::*;
+
+use arch::interrupt::{ExceptionHandler, InterruptHandler};
+
+#[repr(C, packed)]
+pub struct Idtr {
+    limit: u16,
+    base: u64,
+}
+
+impl fmt::LowerHex for Idtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { write!(f, "{:04x}{:016x}", self.limit, self.base) }
+    }
+}
+
+pub struct Idt<'a> {
+    inner: &'a mut [Entry],
+}
+
+impl<'a> Idt<'a> {
+    pub fn with_table(inner: &'a mut [Entry]) -> Idt<'a> {
+        Idt { inner }
+    }
+
+    pub fn idtr(&self) -> Idtr {
+        Idtr {
+            limit: (self.inner.len() * mem::size_of::<Entry>() - 1) as u16,
+            base: self.inner.as_ptr() as usize as u64,
+        }
+    }
+
+    pub fn new_handler(&mut self, num: u8, entry: Entry) {
+        self.inner[num as usize] = entry;
+    }
+
+    fn new_default_handler(&mut self, num: u8, isr: *const ()) {
+        let entry = EntryBuilder::new()
+            .present()
+            .isr(isr)
+            .selector(8)
+            .ring(RingLevel::Ring3)
+            .gate(Gate::Interrupt)
+            .build();
+        self.new_handler(num, entry);
+    }
+
+    pub fn new_exception_handler(&mut self, num: u8, isr: ExceptionHandler) {
+        self.new_default_handler(num, isr as *const ());
+    }
+
+    pub fn new_interrupt_handler(&mut self, num: u8, isr: InterruptHandler) {
+        self.new_default_handler(num, isr as *const ());
+    }
+
+    // like new_exception_handler, but the handler runs on interrupt stack ist
+    pub fn new_exception_handler_on(
+        &mut self,
+        num: u8,
+        isr: ExceptionHandler,
+        ist: u8,
+    ) {
+        let entry = EntryBuilder::new()
+            .present()
+            .isr(isr as *const ())
+            .selector(8)
+            .ring(RingLevel::Ring3)
+            .gate(Gate::Interrupt)
+            .ist(ist)
+            .build();
+        self.new_handler(num, entry);
+    }
+}
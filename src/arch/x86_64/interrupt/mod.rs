@@ -0,0 +1,22 @@
+pub mod idt;
+pub mod exceptions;
+
+use self::idt::Idtr;
+
+#[repr(C, packed)]
+pub struct ExceptionStackFrame {
+    pub rip: usize,
+    pub cs: usize,
+    pub rflags: usize,
+    pub sp: usize,
+    pub ss: usize,
+}
+
+pub type InterruptHandler =
+    unsafe extern "x86-interrupt" fn(&ExceptionStackFrame);
+// the entry stubs in exceptions, they save the registers for the handlers
+pub type ExceptionHandler = unsafe extern "C" fn();
+
+pub unsafe fn lidt(idtr: &Idtr) {
+    asm!("lidtq   $0" : : "*m"(idtr) : "memory" : "volatile");
+}
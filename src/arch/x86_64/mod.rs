@@ -0,0 +1,744 @@
+use multiboot2::{self, ElfSectionFlags};
+use raw_cpuid::CpuId;
+
+use kmain;
+use acpi::{self, Acpi, Rsdp};
+
+pub mod interrupt;
+pub mod paging;
+pub mod segmentation;
+pub mod cpuid;
+pub mod smp;
+
+use mem::frame::{self, Allocator as FrameAllocator, FRAME_SIZE};
+use mem::kmap;
+use mem::page::Allocator as PageAllocator;
+use mem::stack;
+use arch::paging::addr::Virtual;
+use arch::paging::table::{self, ActiveTable};
+use arch::paging::tlb;
+
+// grub leaves us in protected mode, the trampoline makes sure the cpu has
+// long mode, identity maps the first gigabyte and maps it again at
+// KERNEL_BASE with huge pages, enables long mode and jumps to the higher half
+//
+// a cpu without long mode gets a message on the screen and is halted
+global_asm!(
+    r#"
+.set mb2_magic, 0xe85250d6
+.set header_length, header_end - header_start
+.set kernel_base, 0xffffffff80000000
+
+.macro HEADER arch, len
+.align  4
+.long   mb2_magic
+.long   \arch
+.long   \len
+.long   (0x100000000 - (mb2_magic + \arch + \len))
+.endm
+
+.macro END_TAG
+.align  8
+.word   0
+.word   0
+.long   0
+.endm
+
+.section .text.boot, "ax"
+.code32
+header_start:
+HEADER arch=0, len=header_length
+END_TAG
+header_end:
+
+.global _start
+_start:
+        cli
+        cld
+        movl    $stack_end - kernel_base, %esp
+
+        pushfl
+        pushfl
+        xorl    $0x00200000, (%esp)
+        popfl
+        pushfl
+        popl    %eax
+        xorl    (%esp), %eax
+        popfl
+        testl   $0x00200000, %eax
+        jz      boot.no_long_mode
+
+        movl    %ebx, %esi
+        movl    $0x80000000, %eax
+        cpuid
+        cmpl    $0x80000001, %eax
+        jb      boot.no_long_mode
+        movl    $0x80000001, %eax
+        cpuid
+        testl   $(1 << 29), %edx
+        jz      boot.no_long_mode
+        movl    %esi, %ebx
+
+        movl    $boot_pdpt - kernel_base + 3, boot_pml4 - kernel_base
+        movl    $boot_pml4 - kernel_base + 3, boot_pml4 - kernel_base + 510 * 8
+        movl    $boot_pdpt - kernel_base + 3, boot_pml4 - kernel_base + 511 * 8
+        movl    $boot_pd - kernel_base + 3, boot_pdpt - kernel_base
+        movl    $boot_pd - kernel_base + 3, boot_pdpt - kernel_base + 510 * 8
+
+        xorl    %ecx, %ecx
+boot.map_loop:
+        movl    %ecx, %eax
+        shll    $21, %eax
+        orl     $0x83, %eax
+        movl    %eax, boot_pd - kernel_base(, %ecx, 8)
+        incl    %ecx
+        cmpl    $512, %ecx
+        jne     boot.map_loop
+
+        movl    $boot_pml4 - kernel_base, %eax
+        movl    %eax, %cr3
+
+        movl    %cr4, %eax
+        orl     $0x20, %eax
+        movl    %eax, %cr4
+
+        movl    $0xc0000080, %ecx
+        rdmsr
+        orl     $0x100, %eax
+        wrmsr
+
+        movl    %cr0, %eax
+        orl     $0x80010000, %eax
+        movl    %eax, %cr0
+
+        lgdt    boot_gdtr - kernel_base
+        ljmp    $0x08, $boot.long_mode - kernel_base
+
+.code64
+boot.long_mode:
+        movabsq $boot.higher_half, %rax
+        jmpq    *%rax
+
+boot.higher_half:
+        movw    $0x10, %ax
+        movw    %ax, %ds
+        movw    %ax, %es
+        movw    %ax, %fs
+        movw    %ax, %gs
+        movw    %ax, %ss
+
+        movabsq $stack_end, %rsp
+        xorq    %rbp, %rbp
+
+        movabsq $boot_pml4, %rax
+        movq    $0, (%rax)
+        movq    %cr3, %rax
+        movq    %rax, %cr3
+
+        movl    %ebx, %ebx
+        movabsq $kernel_base, %rdi
+        addq    %rbx, %rdi
+        movabsq $kernel_start, %rsi
+        movabsq $kernel_end, %rdx
+
+        call    _rust_start
+
+.code32
+boot.no_long_mode:
+        movl    $boot.no_long_mode_message - kernel_base, %esi
+        movl    $0xb8000, %edi
+        movb    $0x4f, %ah
+boot.print_loop:
+        lodsb
+        testb   %al, %al
+        jz      boot.halt
+        stosw
+        jmp     boot.print_loop
+boot.halt:
+        hlt
+        jmp     boot.halt
+
+boot.no_long_mode_message:
+.asciz  "this cpu doesn't support long mode, the kernel needs a 64-bit cpu"
+
+.align 8
+boot_gdt:
+.quad   0
+.quad   0x00209a0000000000
+.quad   0x0000920000000000
+boot_gdtr:
+.word   boot_gdtr - boot_gdt - 1
+.quad   boot_gdt - kernel_base
+
+.section .bss
+.align 4096
+boot_pml4:
+.fill 4096, 1, 0
+boot_pdpt:
+.fill 4096, 1, 0
+boot_pd:
+.fill 4096, 1, 0
+
+.align 4096
+.global stack_guard
+stack_guard:
+.fill 4096, 1, 0
+stack_start:
+.fill 16384, 1, 0
+stack_end:
+"#
+);
+
+extern "C" {
+    static stack_guard: u8;
+}
+
+#[derive(Debug)]
+pub struct Kinfo {
+    pub kernel_start: usize,
+    pub kernel_end: usize,
+    pub heap_start: usize,
+    pub heap_end: usize,
+    pub free_memory: usize,
+    pub cpuid: Option<CpuId>,
+    pub acpi: Option<Acpi>,
+    _priv: (),
+}
+
+impl Kinfo {
+    pub fn kernel_size(&self) -> usize {
+        self.kernel_end - self.kernel_start
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _rust_start(
+    mb2_addr: usize,
+    kernel_start: usize,
+    kernel_end: usize,
+) -> ! {
+    assert_has_not_been_called!(
+        "_rust_start can only be called from boot code @ _start"
+    );
+
+    // the boot code left its tables behind with the recursive entry set
+    let page_table = ActiveTable::current();
+    tlb::enable_global_pages();
+    table::enable_no_execute();
+
+    let kinfo = kinit(page_table, mb2_addr, kernel_start, kernel_end)
+        .unwrap_or_else(|| unreachable!());
+    kmain(&kinfo);
+
+    loop {}
+}
+
+fn kinit(
+    page_table: ActiveTable<'static>,
+    mb2_addr: usize,
+    kernel_start: usize,
+    kernel_end: usize,
+) -> Option<Kinfo> {
+    use spin::Once;
+    static KINIT: Once<()> = Once::new();
+
+    let mut kinfo = None;
+
+    KINIT.call_once(|| {
+        let mb2 = unsafe { multiboot2::load(mb2_addr) };
+        // the tags are only read before the kernel is remapped
+        let rsdp = unsafe { Rsdp::from_multiboot(mb2.start_address()) };
+
+        let memory_map = mb2.memory_map_tag()
+            .unwrap_or_else(|| panic!("no memory map in mb2 header"));
+
+        let mut frame_alloc = FrameAllocator::new();
+
+        // only available areas are iterated, so reserved, acpi and defective
+        // memory never becomes a frame
+        for area in memory_map.memory_areas() {
+            frame_alloc.add_area(area.start_address()..area.end_address());
+        }
+
+        // the real mode ivt and bios data area
+        frame_alloc.reserve(0..FRAME_SIZE);
+        // the application processors start from here, below 1 MiB
+        frame_alloc.reserve(smp::TRAMPOLINE..smp::TRAMPOLINE + FRAME_SIZE);
+        // the kernel image, including the boot page tables
+        frame_alloc.reserve(
+            kernel_start - kernel::KERNEL_BASE
+                ..kernel_end - kernel::KERNEL_BASE,
+        );
+        // the multiboot information structure, still used below
+        frame_alloc.reserve(
+            mb2.start_address() - kernel::KERNEL_BASE
+                ..mb2.end_address() - kernel::KERNEL_BASE,
+        );
+
+        let page_alloc = PageAllocator::with_used(&page_table);
+        kernel::set_allocator_pair(frame_alloc, page_alloc);
+        kernel::set_page_table(page_table);
+        kmap::init();
+
+        let elf_sections = mb2.elf_sections_tag()
+            .unwrap_or_else(|| panic!("no elf sections in mb2 header"));
+        let sections = elf_sections
+            .sections()
+            .filter(|sect| sect.flags().contains(ElfSectionFlags::ALLOCATED))
+            .map(|sect| {
+                let start = sect.start_address() as usize;
+                let end = sect.end_address() as usize;
+                (start..end, sect.flags())
+            });
+
+        unsafe {
+            kernel::remap_kernel(kernel_start..kernel_end, sections);
+        }
+
+        // the window mapped at boot is full of huge pages, the heap goes
+        // right after it
+        let heap_start = kernel::KERNEL_BASE + kernel::WINDOW_SIZE;
+        let heap_end = heap_start + kernel::HEAP_SIZE;
+
+        unsafe {
+            kernel::init_heap(heap_start, heap_end);
+            kernel::init_gdt();
+            kernel::init_idt();
+        }
+        frame::init_counts();
+
+        let acpi = acpi::init(rsdp);
+
+        // the page below the boot stack is left unmapped, so overflowing it
+        // faults instead of corrupting .bss
+        let guard = Virtual::new(unsafe { &stack_guard as *const _ as usize });
+        unsafe { kernel::page_table() }.unmap(guard).flush();
+        stack::register_guard(guard, "boot");
+
+        let free_memory = unsafe { kernel::frame_alloc().free() } * FRAME_SIZE;
+
+        kinfo = Some(Kinfo {
+            kernel_start,
+            kernel_end,
+            heap_start,
+            heap_end,
+            free_memory,
+            cpuid: Some(CpuId::new()),
+            acpi,
+            _priv: (),
+        });
+    });
+
+    kinfo
+}
+
+pub mod kernel {
+    use core::mem;
+    use core::ops::Range;
+    use core::slice;
+
+    use alloc::allocator::{Alloc, Layout};
+    use alloc::boxed::Box;
+
+    use multiboot2::ElfSectionFlags;
+
+    use spin::{Mutex, MutexGuard, Once};
+
+    use {ALLOCATOR, SLAB};
+
+    use arch::paging::addr::*;
+    use arch::paging::table::{ActiveTable, EntryBuilder, PageSize};
+    use arch::paging::tlb;
+
+    use arch::segmentation::{lgdt, ltr, reload_segments};
+    use arch::segmentation::gdt::{self, Gdt, Gdtr, Tss};
+
+    use arch::interrupt::{exceptions, lidt};
+    use arch::interrupt::idt::{self, Idt, Idtr};
+
+    use drivers::{irq, pic};
+
+    use mem::frame::Allocator as FrameAllocator;
+    use mem::kmap;
+    use mem::page::{Allocator as PageAllocator, HUGE_PAGE_SIZE, PAGE_SIZE};
+    use mem::stack::Stack;
+
+    // the top 2 GiB, where the kernel code model expects the kernel
+    pub const KERNEL_BASE: usize = 0xffffffff80000000;
+    // physical memory mapped at KERNEL_BASE by the boot code
+    pub const WINDOW_SIZE: usize = 0x40000000;
+    pub const HEAP_SIZE: usize = 256 * PAGE_SIZE;
+    // the kmap slots, in the last 16 MiB
+    pub const KMAP_BASE: usize = 0xffffffffff000000;
+    // device registers and firmware tables are mapped from here on
+    pub const MMIO_BASE: usize = 0xfffffffffd000000;
+    // kernel stacks are allocated from here on
+    pub const STACKS_BASE: usize = 0xfffffffffe000000;
+
+    static FRAME_ALLOC: Once<Mutex<FrameAllocator>> = Once::new();
+    static PAGE_ALLOC: Once<Mutex<PageAllocator>> = Once::new();
+    static PAGE_TABLE: Once<Mutex<ActiveTable<'static>>> = Once::new();
+
+    // long mode has no task gates, double faults switch to interrupt stack
+    // DOUBLE_FAULT_IST of TSS instead
+    const TSS_SELECTOR: u16 = 0x18;
+    const DOUBLE_FAULT_IST: u8 = 1;
+    static TSS: Once<Tss> = Once::new();
+    static DOUBLE_FAULT_STACK: Once<Stack> = Once::new();
+
+    pub fn set_page_table(page_table: ActiveTable<'static>) {
+        PAGE_TABLE.call_once(move || Mutex::new(page_table));
+    }
+
+    pub fn set_allocator_pair(frame: FrameAllocator, page: PageAllocator) {
+        FRAME_ALLOC.call_once(move || Mutex::new(frame));
+        PAGE_ALLOC.call_once(move || Mutex::new(page));
+    }
+
+    pub unsafe fn page_table() -> MutexGuard<'static, ActiveTable<'static>> {
+        PAGE_TABLE.try().unwrap().lock()
+    }
+
+    // the try_ accessors are for interrupt and fault handlers, which may
+    // interrupt code that holds the lock, they get None then and have to give
+    // up rather than deadlock
+    pub fn try_page_table() -> Option<MutexGuard<'static, ActiveTable<'static>>>
+    {
+        PAGE_TABLE.try().and_then(|table| table.try_lock())
+    }
+
+    pub unsafe fn frame_alloc() -> MutexGuard<'static, FrameAllocator> {
+        FRAME_ALLOC.try().unwrap().lock()
+    }
+
+    pub fn try_frame_alloc() -> Option<MutexGuard<'static, FrameAllocator>> {
+        FRAME_ALLOC.try().and_then(|alloc| alloc.try_lock())
+    }
+
+    pub unsafe fn page_alloc() -> MutexGuard<'static, PageAllocator> {
+        PAGE_ALLOC.try().unwrap().lock()
+    }
+
+    pub fn try_page_alloc() -> Option<MutexGuard<'static, PageAllocator>> {
+        PAGE_ALLOC.try().and_then(|alloc| alloc.try_lock())
+    }
+
+    // replaces the huge pages that hold the kernel with page tables, so that
+    // every kernel section gets its own permissions: read-only text and
+    // rodata, writable data and bss, only text is executable
+    //
+    // the rest of the window, low memory, the boot tables and free frames,
+    // stays writable but isn't executable anymore
+    pub unsafe fn remap_kernel<I>(kernel: Range<usize>, sections: I)
+    where
+        I: Iterator<Item = (Range<usize>, ElfSectionFlags)>,
+    {
+        assert_has_not_been_called!(
+            "k::arch::kernel::remap_kernel can only be called once"
+        );
+
+        for idx in 0..WINDOW_SIZE / HUGE_PAGE_SIZE {
+            let phys = Physical::new(idx * HUGE_PAGE_SIZE);
+            let virt = KERNEL_BASE + phys.into_inner();
+            let entry = if overlaps(virt, HUGE_PAGE_SIZE, &kernel) {
+                EntryBuilder::new()
+                    .addr(split_huge_page(phys, &kernel))
+                    .present()
+                    .read_write()
+                    .page_size(PageSize::Normal)
+                    .build()
+            } else {
+                EntryBuilder::new()
+                    .addr(phys)
+                    .present()
+                    .read_write()
+                    .global()
+                    .no_execute()
+                    .page_size(PageSize::Huge)
+                    .build()
+            };
+            // invlpg drops the cached huge page and directory entry
+            page_table()
+                .map(Virtual::new(virt), entry, &mut frame_alloc())
+                .flush();
+        }
+
+        let mut table = page_table();
+        let mut batch = tlb::Batch::new();
+        for (range, flags) in sections {
+            // sections that aren't in the higher half aren't loaded there
+            if range.start < KERNEL_BASE {
+                continue;
+            }
+            assert!(
+                range.start >= kernel.start && range.end <= kernel.end,
+                "kernel section {:016x}-{:016x} is outside of the kernel",
+                range.start,
+                range.end,
+            );
+
+            let start = range.start / PAGE_SIZE;
+            let end = (range.end + PAGE_SIZE - 1) / PAGE_SIZE;
+            for idx in start..end {
+                let virt = idx * PAGE_SIZE;
+                let mut builder = EntryBuilder::new()
+                    .addr(Physical::new(virt - KERNEL_BASE))
+                    .present()
+                    .global()
+                    .page_size(PageSize::Normal);
+                if flags.contains(ElfSectionFlags::WRITABLE) {
+                    builder = builder.read_write();
+                }
+                if !flags.contains(ElfSectionFlags::EXECUTABLE) {
+                    builder = builder.no_execute();
+                }
+                batch.add(
+                    table
+                        .remap_page(Virtual::new(virt), builder.build())
+                        .unwrap_or_else(|| unreachable!()),
+                );
+            }
+        }
+        batch.flush();
+    }
+
+    fn overlaps(start: usize, size: usize, range: &Range<usize>) -> bool {
+        start < range.end && start + size > range.start
+    }
+
+    // a page table with the frames of the huge page at phys, writable and not
+    // executable, but the kernel's pages stay executable, it keeps running
+    // from them until remap_kernel gave them their own permissions
+    unsafe fn split_huge_page(
+        phys: Physical,
+        kernel: &Range<usize>,
+    ) -> Physical {
+        let frame = frame_alloc()
+            .allocate()
+            .unwrap_or_else(|| panic!("couldn't allocate a kernel page table"))
+            .leak();
+        let mut slot = kmap::kmap(frame).unwrap_or_else(|| {
+            panic!("no kmap slot for the kernel page table")
+        });
+
+        for (idx, entry) in slot.entries().iter_mut().enumerate() {
+            let addr = phys + idx * PAGE_SIZE;
+            let virt = KERNEL_BASE + addr.into_inner();
+            let mut builder = EntryBuilder::new()
+                .addr(addr)
+                .present()
+                .read_write()
+                .global()
+                .page_size(PageSize::Normal);
+            if !overlaps(virt, PAGE_SIZE, kernel) {
+                builder = builder.no_execute();
+            }
+            *entry = builder.build();
+        }
+        frame
+    }
+
+    pub unsafe fn init_heap(heap_start: usize, heap_end: usize) {
+        static HEAP: Once<()> = Once::new();
+
+        HEAP.call_once(|| {
+            let heap_size = heap_end - heap_start;
+            assert!(
+                heap_size >= PAGE_SIZE,
+                "the heap must be at least {}kB big, is {}kB",
+                PAGE_SIZE / 1024,
+                heap_size / 1024,
+            );
+
+            let mut page_table = page_table();
+            let mut page_alloc = page_alloc();
+            let mut frame_alloc = frame_alloc();
+
+            let pages = heap_size / PAGE_SIZE;
+
+            for i in 0..pages {
+                let addr = heap_start + i * PAGE_SIZE;
+                let page = page_alloc.allocate_at(Virtual::new(addr));
+                let frame = frame_alloc.allocate();
+                assert!(
+                    page.as_ref().map_or(false, |page| *page.addr() == addr),
+                    "couldn't allocate {}-th heap page at {:x}",
+                    i,
+                    addr,
+                );
+                assert!(
+                    frame.is_some(),
+                    "couldn't allocate {}-th heap frame",
+                    i,
+                );
+                // the heap is never freed, so neither are its pages
+                let page = page.unwrap().leak();
+                let frame = frame.unwrap().leak();
+                page_table
+                    .global_map_page(page, frame, &mut frame_alloc)
+                    .flush();
+            }
+
+            ALLOCATOR.init(heap_start, heap_size);
+        });
+    }
+
+    pub unsafe fn init_gdt() {
+        static GDTR: Once<Gdtr> = Once::new();
+        static GDT: Once<Gdt> = Once::new();
+
+        let gdt = GDT.call_once(|| new_gdt(tss()));
+        let gdtr = GDTR.call_once(|| gdt.gdtr());
+
+        lgdt(gdtr);
+        reload_segments(0x8, 0x10);
+        ltr(TSS_SELECTOR);
+    }
+
+    // gives an application processor a gdt and a tss of its own, with a
+    // double fault stack of its own
+    pub unsafe fn init_ap_gdt() {
+        let stack = Stack::new("double fault").unwrap_or_else(|| {
+            panic!("no memory left for the double fault stack")
+        });
+        let mut tss = Tss::new();
+        let top = stack.top().into_inner();
+        tss.set_interrupt_stack(DOUBLE_FAULT_IST as usize, top);
+        // the cpu keeps the stack for as long as it runs
+        mem::forget(stack);
+
+        let tss = &*Box::into_raw(Box::new(tss));
+        let gdt = new_gdt(tss);
+
+        lgdt(&gdt.gdtr());
+        reload_segments(0x8, 0x10);
+        ltr(TSS_SELECTOR);
+    }
+
+    // flat code and data segments and the task state segment
+    unsafe fn new_gdt(tss: &'static Tss) -> Gdt<'static> {
+        let len = 8;
+        let ptr = (&SLAB)
+            .alloc(Layout::from_size_align_unchecked(
+                len * mem::size_of::<gdt::Entry>(),
+                mem::size_of::<gdt::Entry>(),
+            ))
+            .unwrap();
+        let table = slice::from_raw_parts_mut(ptr as *mut _, len);
+
+        for entry in table.iter_mut() {
+            *entry = gdt::Entry::empty()
+        }
+
+        let mut gdt = Gdt::with_table(table);
+        gdt.new_entry(
+            0x8,
+            gdt::EntryBuilder::new()
+                .base(0)
+                .limit(0xfffff)
+                .granularity(gdt::Granularity::Page)
+                .size(64)
+                .present()
+                .ring(gdt::RingLevel::Ring0)
+                .executable()
+                .read_write()
+                .build(),
+        );
+        gdt.new_entry(
+            0x10,
+            gdt::EntryBuilder::new()
+                .base(0)
+                .limit(0xfffff)
+                .granularity(gdt::Granularity::Page)
+                .size(32)
+                .present()
+                .ring(gdt::RingLevel::Ring0)
+                .read_write()
+                .build(),
+        );
+        // the tss descriptor takes up 0x18 and 0x20
+        gdt.new_tss(TSS_SELECTOR, tss);
+        gdt
+    }
+
+    fn tss() -> &'static Tss {
+        let stack = DOUBLE_FAULT_STACK.call_once(|| {
+            Stack::new("double fault").unwrap_or_else(|| {
+                panic!("no memory left for the double fault stack")
+            })
+        });
+        TSS.call_once(|| {
+            let mut tss = Tss::new();
+            let top = stack.top().into_inner();
+            tss.set_interrupt_stack(DOUBLE_FAULT_IST as usize, top);
+            tss
+        })
+    }
+
+    pub unsafe fn init_idt() {
+        static IDTR: Once<Idtr> = Once::new();
+        static IDT: Once<Idt> = Once::new();
+
+        let idt = IDT.call_once(|| {
+            let len = 256;
+            let ptr = (&SLAB)
+                .alloc(Layout::from_size_align_unchecked(
+                    len * mem::size_of::<idt::Entry>(),
+                    mem::size_of::<idt::Entry>(),
+                ))
+                .unwrap();
+            let table = slice::from_raw_parts_mut(ptr as *mut _, len);
+
+            for entry in table.iter_mut() {
+                *entry = idt::Entry::empty()
+            }
+
+            let mut idt = Idt::with_table(table);
+
+            idt.new_exception_handler(0x0, exceptions::de);
+            idt.new_exception_handler(0x1, exceptions::db);
+            idt.new_exception_handler(0x2, exceptions::ni);
+            idt.new_exception_handler(0x3, exceptions::bp);
+            idt.new_exception_handler(0x4, exceptions::of);
+            idt.new_exception_handler(0x5, exceptions::br);
+            idt.new_exception_handler(0x6, exceptions::ud);
+            idt.new_exception_handler(0x7, exceptions::nm);
+            idt.new_exception_handler_on(
+                0x8,
+                exceptions::df,
+                DOUBLE_FAULT_IST,
+            );
+            idt.new_exception_handler(0xa, exceptions::ts);
+            idt.new_exception_handler(0xb, exceptions::np);
+            idt.new_exception_handler(0xc, exceptions::ss);
+            idt.new_exception_handler(0xd, exceptions::gp);
+            idt.new_exception_handler(0xe, exceptions::pf);
+            idt.new_exception_handler(0x10, exceptions::mf);
+            idt.new_exception_handler(0x11, exceptions::ac);
+            idt.new_exception_handler(0x12, exceptions::mc);
+            idt.new_exception_handler(0x13, exceptions::xm);
+            idt.new_exception_handler(0x14, exceptions::ve);
+            idt.new_exception_handler(0x1e, exceptions::sx);
+
+            for (line, stub) in irq::STUBS.iter().enumerate() {
+                idt.new_interrupt_handler(pic::OFFSET + line as u8, *stub);
+            }
+            // a disabled pic and the apic's spurious vector
+            for line in 0..irq::LINES as u8 {
+                let vector = pic::DISABLED_OFFSET + line;
+                idt.new_interrupt_handler(vector, pic::ignore);
+            }
+
+            idt.new_interrupt_handler(0x80, ::syscall::handler);
+
+            idt
+        });
+
+        let idtr = IDTR.call_once(|| idt.idtr());
+        lidt(idtr);
+    }
+}
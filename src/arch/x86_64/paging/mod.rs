@@ -0,0 +1,5 @@
+#[path = "../../x86/paging/addr.rs"]
+pub mod addr;
+pub mod table;
+#[path = "../../x86/paging/tlb.rs"]
+pub mod tlb;
@@ -0,0 +1,719 @@
+use core::slice;
+use core::ops::{Deref, DerefMut, Range};
+
+use x86::shared::control_regs::{cr3, cr3_write};
+
+use arch::paging::addr::*;
+use arch::paging::tlb::Flush;
+use mem::frame::{Allocator as FrameAllocator, FRAME_SIZE};
+use mem::kmap;
+use macros::*;
+
+#[path = "../../../x86/paging/table/entry.rs"]
+pub mod entry;
+pub use self::entry::*;
+
+// entries in a table of any level
+pub const ENTRIES: usize = 512;
+
+// the pml4 is level 4, page tables are level 1
+pub const LEVELS: usize = 4;
+
+// the pml4 fits in a single frame
+pub const ROOT_ENTRIES: usize = ENTRIES;
+pub const ROOT_FRAMES: usize = 1;
+
+// the second to last pml4 entry points back to the pml4, so every table of
+// the active hierarchy shows up somewhere in the 512 GiB at 0xffffff0000000000,
+// the last entry is left for the kernel
+const RECURSIVE_IDX: usize = 0x1fe;
+
+// the canonical hole, addresses in it can't be mapped
+const LOWER_END: usize = 0x0000800000000000;
+const UPPER_START: usize = 0xffff800000000000;
+
+#[inline]
+pub fn index(virt: Virtual, level: usize) -> usize {
+    (virt.into_inner() >> (12 + 9 * (level - 1))) & (ENTRIES - 1)
+}
+
+// bytes mapped by an entry of level
+#[inline]
+fn entry_size(level: usize) -> usize {
+    FRAME_SIZE << (9 * (level - 1))
+}
+
+#[inline]
+pub fn is_recursive(idx: usize) -> bool {
+    idx == RECURSIVE_IDX
+}
+
+#[inline]
+fn sign_extend(addr: usize) -> usize {
+    if addr & LOWER_END != 0 {
+        addr | UPPER_START
+    } else {
+        addr & (LOWER_END - 1)
+    }
+}
+
+// the address of the table of level that covers virt, through the recursive
+// entry
+//
+// every pass through the recursive entry strips one level off the walk
+fn recursive_table(virt: Virtual, level: usize) -> usize {
+    let mut addr = virt.into_inner() & (LOWER_END * 2 - 1);
+    for _ in 0..level {
+        addr = (addr >> 9) | (RECURSIVE_IDX << 39);
+    }
+    sign_extend(addr & !(FRAME_SIZE - 1))
+}
+
+// where the lower level tables of a pml4 can be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Tables {
+    // through the recursive entry, only valid for the active pml4
+    Recursive,
+    // at their physical address plus an offset
+    Offset(usize),
+}
+
+// the entry that maps virt and its level, or the level of the first missing
+// entry on the way down
+enum Leaf {
+    Mapped(Entry, usize),
+    Missing(usize),
+}
+
+struct Table<'a> {
+    inner: &'a mut [Entry],
+    tables: Tables,
+}
+
+impl<'a> Table<'a> {
+    pub unsafe fn new(inner: &'a mut [Entry], tables: Tables) -> Table<'a> {
+        Table { inner, tables }
+    }
+
+    // the table of level that covers virt, if every table above it exists
+    fn table_ptr(&self, level: usize, virt: Virtual) -> Option<*mut Entry> {
+        let mut ptr = self.inner.as_ptr() as *mut Entry;
+        for current in ((level + 1)..(LEVELS + 1)).rev() {
+            let entry = unsafe { *ptr.offset(index(virt, current) as isize) };
+            if !entry.is_used() || entry.is_huge() {
+                return None;
+            }
+            let addr = match self.tables {
+                Tables::Recursive => recursive_table(virt, current - 1),
+                Tables::Offset(offset) => {
+                    entry.into_physical().into_inner() + offset
+                }
+            };
+            ptr = addr as *mut _;
+        }
+        Some(ptr)
+    }
+
+    fn table(&self, level: usize, virt: Virtual) -> Option<&[Entry]> {
+        self.table_ptr(level, virt)
+            .map(|ptr| unsafe { slice::from_raw_parts(ptr, ENTRIES) })
+    }
+
+    fn table_mut(
+        &mut self,
+        level: usize,
+        virt: Virtual,
+    ) -> Option<&mut [Entry]> {
+        self.table_ptr(level, virt)
+            .map(|ptr| unsafe { slice::from_raw_parts_mut(ptr, ENTRIES) })
+    }
+
+    // tables for user pages have to allow user access themselves, the leaf
+    // entries decide what's actually allowed
+    fn table_or_create(
+        &mut self,
+        level: usize,
+        virt: Virtual,
+        user: bool,
+        frames: &mut FrameAllocator,
+    ) -> &mut [Entry] {
+        for current in ((level + 1)..(LEVELS + 1)).rev() {
+            let idx = index(virt, current);
+            let entry = self.table(current, virt).unwrap()[idx];
+            assert!(
+                !entry.is_huge(),
+                "{:016x} is already mapped by a huge page",
+                virt,
+            );
+
+            if !entry.is_used() {
+                // tables are never freed, not even when they become empty
+                let frame = frames
+                    .allocate()
+                    .unwrap_or_else(|| panic!("out of frames for page tables"));
+                let mut builder = EntryBuilder::new()
+                    .addr(frame.leak())
+                    .present()
+                    .read_write()
+                    .page_size(PageSize::Normal);
+                if user {
+                    builder = builder.user();
+                }
+                self.table_mut(current, virt).unwrap()[idx] = builder.build();
+
+                for entry in self.table_mut(current - 1, virt).unwrap() {
+                    *entry = Entry::empty();
+                }
+            } else if user && !entry.flags().contains(Flags::USER) {
+                let entry = EntryBuilder::new()
+                    .addr(entry.into_physical())
+                    .flags(entry.flags() | Flags::USER)
+                    .build();
+                self.table_mut(current, virt).unwrap()[idx] = entry;
+            }
+        }
+
+        self.table_mut(level, virt).unwrap()
+    }
+
+    fn leaf(&self, virt: Virtual) -> Leaf {
+        for level in (1..(LEVELS + 1)).rev() {
+            let entry = self.table(level, virt).unwrap()[index(virt, level)];
+            if !entry.is_used() {
+                return Leaf::Missing(level);
+            }
+            if level == 1 || entry.is_huge() {
+                return Leaf::Mapped(entry, level);
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn translate(&self, virt: Virtual) -> Option<(Physical, Flags)> {
+        match self.leaf(virt) {
+            Leaf::Mapped(entry, level) => {
+                let offset = virt.into_inner() & (entry_size(level) - 1);
+                Some((entry.into_physical() + offset, entry.flags()))
+            }
+            Leaf::Missing(_) => None,
+        }
+    }
+
+    pub fn mappings<'t>(&'t self, range: Range<Virtual>) -> Mappings<'t, 'a> {
+        Mappings {
+            table: self,
+            next: Some(range.start.into_inner()),
+            end: range.end.into_inner(),
+        }
+    }
+
+    pub fn dump(&self) {
+        for (idx, entry) in self.inner.iter().enumerate() {
+            if !entry.is_used() {
+                continue;
+            }
+
+            let virt = sign_extend(idx << 39);
+            if idx == RECURSIVE_IDX && self.tables == Tables::Recursive {
+                kprintln!(
+                    "{:016x} recursive -> {:08x}",
+                    virt,
+                    entry.into_physical(),
+                );
+                continue;
+            }
+
+            kprintln!(
+                "{:016x} pdpt @ {:08x} {:?}",
+                virt,
+                entry.into_physical(),
+                entry.flags(),
+            );
+
+            // print runs of contiguous pages with equal flags as one line
+            let range = Virtual::new(virt)
+                ..Virtual::new(virt + (entry_size(LEVELS) - 1));
+            let mut run: Option<(Mapping, usize)> = None;
+            for mapping in self.mappings(range) {
+                run = match run {
+                    Some((first, count)) if first.is_run(count, &mapping) => {
+                        Some((first, count + 1))
+                    }
+                    Some((first, count)) => {
+                        dump_run(first, count);
+                        Some((mapping, 1))
+                    }
+                    None => Some((mapping, 1)),
+                };
+            }
+            if let Some((first, count)) = run {
+                dump_run(first, count);
+            }
+        }
+    }
+
+    // maps a huge page, creating the tables above it if needed
+    pub fn map(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let user = entry.flags().contains(Flags::USER);
+        let table = self.table_or_create(2, virt, user, frames);
+        let idx = index(virt, 2);
+        let old = if table[idx].is_used() {
+            Some(table[idx])
+        } else {
+            None
+        };
+        table[idx] = entry;
+        old
+    }
+
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let user = entry.flags().contains(Flags::USER);
+        let table = self.table_or_create(1, virt, user, frames);
+        let idx = index(virt, 1);
+        let old = if table[idx].is_used() {
+            Some(table[idx])
+        } else {
+            None
+        };
+        table[idx] = entry;
+        old
+    }
+
+    // like map_page, but only into a page table that exists already, so no
+    // frames are needed, None if there's no such table
+    pub fn remap_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+    ) -> Option<Option<Entry>> {
+        let table = self.table_mut(1, virt)?;
+        let idx = index(virt, 1);
+        let old = if table[idx].is_used() {
+            Some(table[idx])
+        } else {
+            None
+        };
+        table[idx] = entry;
+        Some(old)
+    }
+
+    pub fn default_map(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let entry = EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .page_size(PageSize::Huge)
+            .build();
+
+        self.map(virt, entry, frames)
+    }
+
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let entry = EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .page_size(PageSize::Normal)
+            .build();
+
+        self.map_page(virt, entry, frames)
+    }
+
+    // kernel pages are global, so they stay cached across table switches
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        let entry = EntryBuilder::new()
+            .addr(phys)
+            .present()
+            .read_write()
+            .global()
+            .page_size(PageSize::Normal)
+            .build();
+
+        self.map_page(virt, entry, frames)
+    }
+
+    // unmaps whatever maps virt, a 4 KiB page or a whole huge page
+    pub fn unmap(&mut self, virt: Virtual) -> Option<Entry> {
+        let level = match self.leaf(virt) {
+            Leaf::Mapped(_, level) => level,
+            Leaf::Missing(_) => return None,
+        };
+
+        let table = self.table_mut(level, virt).unwrap();
+        let idx = index(virt, level);
+        let old = table[idx];
+        table[idx] = Entry::empty();
+        Some(old)
+    }
+
+    pub fn is_used(&self, virt: Virtual) -> bool {
+        match self.leaf(virt) {
+            Leaf::Mapped(..) => true,
+            Leaf::Missing(_) => false,
+        }
+    }
+
+    pub unsafe fn reset_cache(&mut self) {
+        cr3_write(cr3());
+    }
+}
+
+fn dump_run(first: Mapping, count: usize) {
+    kprintln!(
+        "  {:016x}-{:016x} -> {:08x} {:?}",
+        first.virt,
+        first.virt.into_inner() + count * first.size.bytes() - 1,
+        first.phys,
+        first.flags,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mapping {
+    pub virt: Virtual,
+    pub phys: Physical,
+    pub size: PageSize,
+    pub flags: Flags,
+}
+
+impl Mapping {
+    // whether next directly follows count pages starting at self
+    fn is_run(&self, count: usize, next: &Mapping) -> bool {
+        let offset = count * self.size.bytes();
+        next.size == self.size
+            && next.flags == self.flags
+            && next.virt == self.virt + offset
+            && next.phys == self.phys + offset
+    }
+}
+
+// iterates over every present page in a range, huge pages are returned once
+// with their aligned address
+//
+// gigabyte pages are never created, so they aren't reported either
+pub struct Mappings<'t, 'a: 't> {
+    table: &'t Table<'a>,
+    next: Option<usize>,
+    end: usize,
+}
+
+impl<'t, 'a: 't> Iterator for Mappings<'t, 'a> {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        while let Some(addr) = self.next {
+            if addr >= self.end {
+                break;
+            }
+
+            if addr >= LOWER_END && addr < UPPER_START {
+                self.next = Some(UPPER_START);
+                continue;
+            }
+
+            let virt = Virtual::new(addr);
+
+            // the recursive entry maps the tables, not actual pages
+            let recursive = is_recursive(index(virt, LEVELS))
+                && self.table.tables == Tables::Recursive;
+            let leaf = if recursive {
+                Leaf::Missing(LEVELS)
+            } else {
+                self.table.leaf(virt)
+            };
+
+            let (entry, level) = match leaf {
+                Leaf::Mapped(entry, level) => (entry, level),
+                Leaf::Missing(level) => (Entry::empty(), level),
+            };
+            let size = entry_size(level);
+            let start = addr & !(size - 1);
+            self.next = start.checked_add(size);
+
+            let page_size = match level {
+                1 => PageSize::Normal,
+                2 => PageSize::Huge,
+                _ => continue,
+            };
+            if entry.is_used() {
+                return Some(Mapping {
+                    virt: Virtual::new(start),
+                    phys: entry.into_physical(),
+                    size: page_size,
+                    flags: entry.flags(),
+                });
+            }
+        }
+
+        self.next = None;
+        None
+    }
+}
+
+impl<'a> Deref for Table<'a> {
+    type Target = [Entry];
+
+    fn deref(&self) -> &[Entry] {
+        self.inner
+    }
+}
+
+impl<'a> DerefMut for Table<'a> {
+    fn deref_mut(&mut self) -> &mut [Entry] {
+        self.inner
+    }
+}
+
+fn recursive_entry(phys: Physical) -> Entry {
+    EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .read_write()
+        .page_size(PageSize::Normal)
+        .build()
+}
+
+pub struct ActiveTable<'a> {
+    inner: Table<'a>,
+}
+
+impl<'a> ActiveTable<'a> {
+    // the hierarchy in cr3, which must already have the recursive entry, e.g.
+    // the one set up by the boot code
+    pub unsafe fn current() -> ActiveTable<'a> {
+        let pml4 = recursive_table(Virtual::new(0), LEVELS);
+        let inner = Table::new(
+            slice::from_raw_parts_mut(pml4 as *mut _, ENTRIES),
+            Tables::Recursive,
+        );
+        ActiveTable { inner }
+    }
+
+    pub fn map(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.map(virt, entry, frames))
+    }
+
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.map_page(virt, entry, frames))
+    }
+
+    pub fn remap_page(&mut self, virt: Virtual, entry: Entry) -> Option<Flush> {
+        self.inner
+            .remap_page(virt, entry)
+            .map(|old| Flush::new(virt, old))
+    }
+
+    pub fn default_map(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.default_map(virt, phys, frames))
+    }
+
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.default_map_page(virt, phys, frames))
+    }
+
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Flush {
+        Flush::new(virt, self.inner.global_map_page(virt, phys, frames))
+    }
+
+    pub fn unmap(&mut self, virt: Virtual) -> Flush {
+        Flush::new(virt, self.inner.unmap(virt))
+    }
+
+    pub fn is_used(&self, virt: Virtual) -> bool {
+        // the recursive entry maps the tables themselves
+        is_recursive(index(virt, LEVELS)) || self.inner.is_used(virt)
+    }
+
+    pub fn translate(&self, virt: Virtual) -> Option<(Physical, Flags)> {
+        self.inner.translate(virt)
+    }
+
+    pub fn mappings<'t>(&'t self, range: Range<Virtual>) -> Mappings<'t, 'a> {
+        self.inner.mappings(range)
+    }
+
+    pub fn dump(&self) {
+        self.inner.dump()
+    }
+
+    // flushes every non-global translation, prefer flushing single pages
+    pub fn reset_cache(&mut self) {
+        unsafe {
+            self.inner.reset_cache();
+        }
+    }
+}
+
+pub struct InactiveTable<'a> {
+    inner: Table<'a>,
+}
+
+impl<'a> InactiveTable<'a> {
+    // the lower level tables are expected at their physical address plus
+    // offset
+    pub fn with_offset(
+        inner: &'a mut [Entry],
+        offset: usize,
+    ) -> InactiveTable<'a> {
+        assert!(
+            inner.len() == ENTRIES,
+            "pml4 must have {} entries, is {}",
+            ENTRIES,
+            inner.len()
+        );
+        InactiveTable {
+            inner: unsafe { Table::new(inner, Tables::Offset(offset)) },
+        }
+    }
+
+    fn physical(&self) -> Physical {
+        let offset = match self.inner.tables {
+            Tables::Offset(offset) => offset,
+            Tables::Recursive => unreachable!(),
+        };
+        Physical::new(self.inner.as_ptr() as usize - offset)
+    }
+
+    pub fn into_physical(self) -> Physical {
+        self.physical()
+    }
+
+    pub fn map(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.map(virt, entry, frames)
+    }
+
+    pub fn map_page(
+        &mut self,
+        virt: Virtual,
+        entry: Entry,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.map_page(virt, entry, frames)
+    }
+
+    pub fn default_map(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.default_map(virt, phys, frames)
+    }
+
+    pub fn default_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.default_map_page(virt, phys, frames)
+    }
+
+    pub fn global_map_page(
+        &mut self,
+        virt: Virtual,
+        phys: Physical,
+        frames: &mut FrameAllocator,
+    ) -> Option<Entry> {
+        self.inner.global_map_page(virt, phys, frames)
+    }
+
+    pub fn unmap(&mut self, virt: Virtual) -> Option<Entry> {
+        self.inner.unmap(virt)
+    }
+
+    pub fn is_used(&self, virt: Virtual) -> bool {
+        self.inner.is_used(virt)
+    }
+
+    #[must_use]
+    pub unsafe fn load(mut self) -> ActiveTable<'a> {
+        let phys = self.physical();
+        self.inner[RECURSIVE_IDX] = recursive_entry(phys);
+
+        // always reloaded, even if cr3 already holds phys, the tlb may still
+        // have translations through the recursive entry from before
+        cr3_write(phys.into_inner());
+
+        ActiveTable::current()
+    }
+}
+
+// clears the pml4 of a new hierarchy at root, except for its recursive entry
+pub fn init_root(root: Physical) {
+    let mut slot = kmap::kmap(root)
+        .unwrap_or_else(|| panic!("no kmap slot for a new pml4"));
+    for (idx, entry) in slot.entries().iter_mut().enumerate() {
+        *entry = if is_recursive(idx) {
+            recursive_entry(root)
+        } else {
+            Entry::empty()
+        };
+    }
+}
+
+pub fn active_root() -> Physical {
+    Physical::new(unsafe { cr3() })
+}
+
+// the root must have been set up by init_root
+pub unsafe fn load_root(root: Physical) {
+    if cr3() != root.into_inner() {
+        cr3_write(root.into_inner());
+    }
+}
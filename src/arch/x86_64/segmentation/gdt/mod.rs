@@ -0,0 +1,59 @@
+use core::mem;
+use core::fmt;
+
+// code and data descriptors look the same as in protected mode, only the
+// long bit is new
+#[path = "../../../x86/segmentation/gdt/entry.rs"]
+pub mod entry;
+pub use self::entry::*;
+
+pub mod tss;
+pub use self::tss::Tss;
+
+const ENTRY_SIZE: usize = 8;
+
+#[repr(C, packed)]
+pub struct Gdtr {
+    limit: u16,
+    base: u64,
+}
+
+impl fmt::LowerHex for Gdtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { write!(f, "{:04x}{:016x}", self.limit, self.base) }
+    }
+}
+
+pub struct Gdt<'a> {
+    inner: &'a mut [Entry],
+}
+
+impl<'a> Gdt<'a> {
+    pub fn with_table(inner: &'a mut [Entry]) -> Gdt<'a> {
+        Gdt { inner }
+    }
+
+    pub fn gdtr(&self) -> Gdtr {
+        Gdtr {
+            limit: (self.inner.len() * mem::size_of::<Entry>() - 1) as u16,
+            base: self.inner.as_ptr() as usize as u64,
+        }
+    }
+
+    pub fn new_entry(&mut self, num: u16, entry: Entry) {
+        assert!(
+            num & (ENTRY_SIZE as u16 - 1) == 0,
+            "gdt entry num must be a multiple of 8"
+        );
+        self.inner[num as usize / ENTRY_SIZE] = entry;
+    }
+
+    // the tss must stay where it is for as long as the gdt is loaded, its
+    // descriptor takes up num and the entry after it
+    pub fn new_tss(&mut self, num: u16, tss: &'static Tss) {
+        let entry = EntryBuilder::new().tss(tss).ring(RingLevel::Ring0).build();
+        self.new_entry(num, entry);
+        let upper = Entry::upper_base(tss as *const Tss as usize);
+        self.new_entry(num + ENTRY_SIZE as u16, upper);
+    }
+}
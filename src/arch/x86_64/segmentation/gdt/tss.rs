@@ -0,0 +1,48 @@
+// long mode has no hardware task switching, the tss only holds the stacks the
+// cpu switches to: one per ring for privilege changes and up to seven
+// interrupt stacks that idt entries can ask for
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct Tss {
+    _reserved_1: u32,
+    pub rsp: [u64; 3],
+    _reserved_2: u64,
+    // ist[0] is interrupt stack 1, 0 means no ist in an idt entry
+    pub ist: [u64; 7],
+    _reserved_3: u64,
+    _reserved_4: u16,
+    pub iomap_base: u16,
+}
+
+// the io bitmap would follow the tss, pointing past the limit means there is
+// none
+const SIZE: u16 = 104;
+
+impl Tss {
+    pub fn new() -> Tss {
+        Tss {
+            _reserved_1: 0,
+            rsp: [0; 3],
+            _reserved_2: 0,
+            ist: [0; 7],
+            _reserved_3: 0,
+            _reserved_4: 0,
+            iomap_base: SIZE,
+        }
+    }
+
+    // the stack the cpu switches to when an interrupt leaves an outer ring
+    pub fn set_kernel_stack(&mut self, rsp: usize) {
+        self.rsp[0] = rsp as u64;
+    }
+
+    // idx counts from 1, like the ist field of idt entries
+    pub fn set_interrupt_stack(&mut self, idx: usize, rsp: usize) {
+        assert!(
+            idx >= 1 && idx <= 7,
+            "interrupt stacks are numbered 1 to 7, not {}",
+            idx,
+        );
+        self.ist[idx - 1] = rsp as u64;
+    }
+}
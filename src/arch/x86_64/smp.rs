@@ -0,0 +1,149 @@
+use core::slice;
+
+use x86::shared::msr::{rdmsr, wrmsr, IA32_EFER, IA32_GS_BASE};
+
+// where the trampoline is copied to, the startup ipi takes its page number
+pub const TRAMPOLINE: usize = 0x8000;
+
+// long mode active, read-only
+const EFER_LMA: u64 = 1 << 10;
+
+// an application processor starts in real mode at TRAMPOLINE, the trampoline
+// loads a gdt with 32 and 64-bit code, goes through protected mode into long
+// mode with the bootstrap processor's tables and calls the entry with arg on
+// the stack given in trampoline_args
+//
+// it's copied to TRAMPOLINE before it runs, so every address in it is
+// relative to that, the page tables must be in the first 4 GiB
+global_asm!(
+    r#"
+.set trampoline_base, 0x8000
+
+.macro FAR_JUMP selector, label
+        ljmpl   $\selector, $(\label - trampoline_start + trampoline_base)
+.endm
+
+.section .rodata
+.code16
+.global trampoline_start
+trampoline_start:
+        cli
+        cld
+        xorw    %ax, %ax
+        movw    %ax, %ds
+
+        lgdtl   trampoline_gdtr - trampoline_start + trampoline_base
+
+        movl    %cr0, %eax
+        orl     $1, %eax
+        movl    %eax, %cr0
+
+        FAR_JUMP 0x08, trampoline.protected
+
+.code32
+trampoline.protected:
+        movw    $0x10, %ax
+        movw    %ax, %ds
+        movw    %ax, %es
+        movw    %ax, %ss
+
+        movl    trampoline_cr4 - trampoline_start + trampoline_base, %eax
+        movl    %eax, %cr4
+
+        movl    trampoline_cr3 - trampoline_start + trampoline_base, %eax
+        movl    %eax, %cr3
+
+        movl    $0xc0000080, %ecx
+        movl    trampoline_efer - trampoline_start + trampoline_base, %eax
+        xorl    %edx, %edx
+        wrmsr
+
+        movl    %cr0, %eax
+        orl     $0x80010000, %eax
+        movl    %eax, %cr0
+
+        FAR_JUMP 0x18, trampoline.long_mode
+
+.code64
+trampoline.long_mode:
+        movq    trampoline_stack - trampoline_start + trampoline_base, %rsp
+        xorq    %rbp, %rbp
+        movq    trampoline_arg - trampoline_start + trampoline_base, %rdi
+        movq    trampoline_entry - trampoline_start + trampoline_base, %rax
+        callq   *%rax
+
+.align 8
+trampoline_gdt:
+.quad   0
+.quad   0x00cf9a000000ffff
+.quad   0x00cf92000000ffff
+.quad   0x00209a0000000000
+trampoline_gdtr:
+.word   trampoline_gdtr - trampoline_gdt - 1
+.long   trampoline_gdt - trampoline_start + trampoline_base
+
+.align 8
+.global trampoline_args
+trampoline_args:
+trampoline_cr3:
+.quad   0
+trampoline_cr4:
+.quad   0
+trampoline_efer:
+.quad   0
+trampoline_stack:
+.quad   0
+trampoline_entry:
+.quad   0
+trampoline_arg:
+.quad   0
+.global trampoline_end
+trampoline_end:
+"#
+);
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_args: u8;
+    static trampoline_end: u8;
+}
+
+// the trampoline as it's copied to TRAMPOLINE
+pub fn trampoline() -> &'static [u8] {
+    unsafe {
+        let start = &trampoline_start as *const u8;
+        let len = &trampoline_end as *const u8 as usize - start as usize;
+        slice::from_raw_parts(start, len)
+    }
+}
+
+// where in the trampoline its arguments are
+pub fn args_offset() -> usize {
+    unsafe {
+        &trampoline_args as *const u8 as usize
+            - &trampoline_start as *const u8 as usize
+    }
+}
+
+// what the trampoline writes to efer, long mode and whatever else the
+// bootstrap processor enabled
+pub fn efer() -> usize {
+    unsafe { (rdmsr(IA32_EFER) & !EFER_LMA) as usize }
+}
+
+// points gs at the per-cpu block at addr, reloading gs clears it again
+pub unsafe fn set_percpu(addr: usize, _size: usize) {
+    wrmsr(IA32_GS_BASE, addr as u64);
+}
+
+// whether set_percpu ran on this cpu, the gs base is 0 until then
+pub fn has_percpu() -> bool {
+    unsafe { rdmsr(IA32_GS_BASE) != 0 }
+}
+
+// the per-cpu block starts with its own address, set_percpu must have run
+pub unsafe fn percpu() -> usize {
+    let addr: usize;
+    asm!("movq    %gs:0, $0" : "=r"(addr) : : : "volatile");
+    addr
+}
@@ -0,0 +1,108 @@
+use core::ptr;
+
+use arch::paging::addr::Virtual;
+
+// the registers are reached through a select and a window register
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const ID: u32 = 0x00;
+const VERSION: u32 = 0x01;
+// two registers per input, the low one first
+const REDIRECTION: u32 = 0x10;
+
+bitflags! {
+    // the low half of a redirection entry besides the vector, the delivery
+    // mode is always fixed
+    pub struct RedirectionFlags: u32 {
+        const MASKED = 1 << 16;
+        const LEVEL = 1 << 15;
+        const ACTIVE_LOW = 1 << 13;
+        const LOGICAL = 1 << 11;
+    }
+}
+
+// an i/o apic, its inputs are the global system interrupts from gsi_base on
+#[derive(Debug)]
+pub struct IoApic {
+    base: Virtual,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    // base must be the uncached mapping of the registers
+    pub unsafe fn new(base: Virtual, gsi_base: u32) -> IoApic {
+        IoApic { base, gsi_base }
+    }
+
+    fn read(&mut self, reg: u32) -> u32 {
+        let base = self.base.into_inner();
+        unsafe {
+            ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+            ptr::read_volatile((base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&mut self, reg: u32, value: u32) {
+        let base = self.base.into_inner();
+        unsafe {
+            ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+            ptr::write_volatile((base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    pub fn id(&mut self) -> u8 {
+        ((self.read(ID) >> 24) & 0x0f) as u8
+    }
+
+    pub fn inputs(&mut self) -> u32 {
+        ((self.read(VERSION) >> 16) & 0xff) + 1
+    }
+
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    // the input gsi arrives at, None if it belongs to another i/o apic
+    pub fn input(&mut self, gsi: u32) -> Option<u32> {
+        let input = gsi.checked_sub(self.gsi_base)?;
+        if input < self.inputs() {
+            Some(input)
+        } else {
+            None
+        }
+    }
+
+    // sends input to vector on the local apic with id dest
+    pub fn set_redirection(
+        &mut self,
+        input: u32,
+        vector: u8,
+        flags: RedirectionFlags,
+        dest: u8,
+    ) {
+        let reg = REDIRECTION + input * 2;
+        // masked while it's half written
+        self.write(reg, RedirectionFlags::MASKED.bits());
+        self.write(reg + 1, (dest as u32) << 24);
+        self.write(reg, vector as u32 | flags.bits());
+    }
+
+    pub fn mask(&mut self, input: u32) {
+        let reg = REDIRECTION + input * 2;
+        let low = self.read(reg) | RedirectionFlags::MASKED.bits();
+        self.write(reg, low);
+    }
+
+    pub fn unmask(&mut self, input: u32) {
+        let reg = REDIRECTION + input * 2;
+        let low = self.read(reg) & !RedirectionFlags::MASKED.bits();
+        self.write(reg, low);
+    }
+
+    pub fn mask_all(&mut self) {
+        for input in 0..self.inputs() {
+            self.mask(input);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use core::ptr;
+
+use arch::paging::addr::Virtual;
+
+// register offsets, every register is 32 bits wide and 16-byte aligned
+const ID: usize = 0x20;
+const VERSION: usize = 0x30;
+const TPR: usize = 0x80;
+const EOI: usize = 0xb0;
+const SVR: usize = 0xf0;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_ERROR: usize = 0x370;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+// interrupt command register bits
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_LEVEL: u32 = 1 << 15;
+
+// the local apic of whichever cpu accesses it, every cpu sees its own at the
+// same address
+#[derive(Debug)]
+pub struct LocalApic {
+    base: Virtual,
+}
+
+impl LocalApic {
+    // base must be the uncached mapping of the registers
+    pub unsafe fn new(base: Virtual) -> LocalApic {
+        LocalApic { base }
+    }
+
+    fn read(&self, reg: usize) -> u32 {
+        let reg = (self.base.into_inner() + reg) as *const u32;
+        unsafe { ptr::read_volatile(reg) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        let reg = (self.base.into_inner() + reg) as *mut u32;
+        unsafe { ptr::write_volatile(reg, value) }
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(ID) >> 24) as u8
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(VERSION) as u8
+    }
+
+    // accepts interrupts of every priority, spurious ones arrive at vector
+    // spurious
+    //
+    // lint0 carries the pic's interrupts in virtual wire mode, it's masked
+    // with the timer and error interrupts
+    pub fn enable(&self, spurious: u8) {
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_ERROR, LVT_MASKED);
+        self.write(TPR, 0);
+        self.write(SVR, SVR_ENABLE | spurious as u32);
+    }
+
+    pub fn eoi(&self) {
+        self.write(EOI, 0);
+    }
+
+    // sends an inter-processor interrupt to the cpu with apic id dest and
+    // waits until it has been delivered
+    fn send_ipi(&self, dest: u8, command: u32) {
+        self.write(ICR_HIGH, (dest as u32) << 24);
+        self.write(ICR_LOW, command);
+        while self.read(ICR_LOW) & ICR_PENDING != 0 {}
+    }
+
+    // resets the cpu with apic id dest, which then waits for a startup ipi
+    pub fn send_init(&self, dest: u8) {
+        self.send_ipi(dest, ICR_INIT | ICR_LEVEL | ICR_ASSERT);
+        self.send_ipi(dest, ICR_INIT | ICR_LEVEL);
+    }
+
+    // starts the cpu with apic id dest in real mode at page * 0x1000, which
+    // must be below 1 MiB
+    pub fn send_startup(&self, dest: u8, page: u8) {
+        self.send_ipi(dest, ICR_STARTUP | page as u32);
+    }
+}
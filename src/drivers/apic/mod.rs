@@ -0,0 +1,173 @@
+use alloc::vec::Vec;
+
+use spin::{Mutex, MutexGuard, Once};
+
+use x86::shared::msr::{rdmsr, wrmsr, IA32_APIC_BASE};
+
+use arch::Kinfo;
+use arch::paging::addr::{PhysAddr, Physical};
+use drivers::irq::{without_interrupts, LINES};
+use drivers::pic;
+use mem::mmio;
+use mem::page::PAGE_SIZE;
+
+pub mod local;
+pub mod io;
+
+pub use self::local::LocalApic;
+pub use self::io::{IoApic, RedirectionFlags};
+
+// the last vector, pic::ignore counts what arrives there
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+// where the i/o apic usually is, for when there's no madt
+const DEFAULT_IO_APIC: PhysAddr = 0xfec00000;
+
+// bits of the apic base msr
+const BASE_ENABLE: u64 = 1 << 11;
+const BASE_MASK: u64 = 0xfffff000;
+
+static LOCAL: Once<LocalApic> = Once::new();
+static IO: Once<Mutex<Vec<IoApic>>> = Once::new();
+static ROUTES: Once<[Route; LINES]> = Once::new();
+
+// where an isa irq line ends up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Route {
+    gsi: u32,
+    flags: RedirectionFlags,
+}
+
+pub fn available(kinfo: &Kinfo) -> bool {
+    kinfo
+        .cpuid
+        .as_ref()
+        .and_then(|cpuid| cpuid.get_feature_info())
+        .map_or(false, |info| info.has_apic())
+}
+
+// true once the apic routes the irqs instead of the pic
+pub fn enabled() -> bool {
+    IO.try().is_some()
+}
+
+// takes over irq routing from the pic, which is masked and moved out of the
+// way, false if there's no apic and the pic stays in charge
+//
+// the apics are taken from the madt, without one the default addresses and
+// isa routing are assumed
+//
+// irqs registered before have to be registered again
+pub fn init(kinfo: &Kinfo) -> bool {
+    if !available(kinfo) {
+        return false;
+    }
+
+    let madt = kinfo.acpi.as_ref().and_then(|acpi| acpi.madt.as_ref());
+    let base = unsafe { rdmsr(IA32_APIC_BASE) };
+
+    let local = madt.map_or(
+        Physical::new((base & BASE_MASK) as PhysAddr),
+        |madt| madt.local_apic,
+    );
+    let local = match mmio::map(local, PAGE_SIZE) {
+        Some(local) => local,
+        None => return false,
+    };
+
+    let mut io = Vec::new();
+    match madt {
+        Some(madt) => for info in madt.io_apics.iter() {
+            if let Some(addr) = mmio::map(info.addr, PAGE_SIZE) {
+                io.push(unsafe { IoApic::new(addr, info.gsi_base) });
+            }
+        },
+        None => {
+            if let Some(addr) =
+                mmio::map(Physical::new(DEFAULT_IO_APIC), PAGE_SIZE)
+            {
+                io.push(unsafe { IoApic::new(addr, 0) });
+            }
+        }
+    }
+    if io.is_empty() {
+        return false;
+    }
+
+    let mut routes = [Route {
+        gsi: 0,
+        flags: RedirectionFlags::empty(),
+    }; LINES];
+    for (line, route) in routes.iter_mut().enumerate() {
+        route.gsi = line as u32;
+        let over = madt.and_then(|madt| madt.source_override(line as u8));
+        if let Some(over) = over {
+            route.gsi = over.gsi;
+            if over.active_low() {
+                route.flags |= RedirectionFlags::ACTIVE_LOW;
+            }
+            if over.level() {
+                route.flags |= RedirectionFlags::LEVEL;
+            }
+        }
+    }
+    ROUTES.call_once(|| routes);
+
+    // without a madt the pics are assumed to be there
+    let pics = madt.map_or(true, |madt| madt.has_pics());
+
+    without_interrupts(|| {
+        if pics {
+            pic::disable();
+        }
+
+        unsafe { wrmsr(IA32_APIC_BASE, base | BASE_ENABLE) };
+        let local = LOCAL.call_once(|| unsafe { LocalApic::new(local) });
+        local.enable(SPURIOUS_VECTOR);
+
+        for io in io.iter_mut() {
+            io.mask_all();
+        }
+        IO.call_once(|| Mutex::new(io));
+    });
+    true
+}
+
+pub fn local() -> Option<&'static LocalApic> {
+    LOCAL.try()
+}
+
+fn route(line: u8) -> Route {
+    ROUTES.try().expect("apic used before apic::init")[line as usize]
+}
+
+fn io_apics() -> MutexGuard<'static, Vec<IoApic>> {
+    IO.try().expect("apic used before apic::init").lock()
+}
+
+// routes isa irq line to the boot cpu, at the vector the pic used for it so
+// the stubs in irq serve both
+pub fn unmask(line: u8) {
+    let dest = local().expect("apic used before apic::init").id();
+    let route = route(line);
+    let vector = pic::OFFSET + line;
+
+    for io in io_apics().iter_mut() {
+        if let Some(input) = io.input(route.gsi) {
+            io.set_redirection(input, vector, route.flags, dest);
+        }
+    }
+}
+
+pub fn mask(line: u8) {
+    let route = route(line);
+    for io in io_apics().iter_mut() {
+        if let Some(input) = io.input(route.gsi) {
+            io.mask(input);
+        }
+    }
+}
+
+pub fn eoi() {
+    local().expect("apic used before apic::init").eoi();
+}
@@ -0,0 +1,142 @@
+use alloc::vec::Vec;
+
+use spin::{Mutex, Once};
+
+use x86::shared::irq;
+
+use arch::interrupt::{ExceptionStackFrame, InterruptHandler};
+use drivers::{apic, pic};
+
+// the isa lines, the 8259 pair has 8 each
+pub const LINES: usize = 16;
+
+// runs with interrupts disabled, every handler on a shared line runs for each
+// interrupt, so it has to check whether its device raised it
+pub type IrqHandler = fn();
+
+static HANDLERS: Once<Mutex<[Vec<IrqHandler>; LINES]>> = Once::new();
+
+fn handlers() -> &'static Mutex<[Vec<IrqHandler>; LINES]> {
+    HANDLERS.call_once(|| Mutex::new(Default::default()))
+}
+
+// the interrupt flag in eflags
+const INTERRUPT_FLAG: usize = 0x200;
+
+// whether this cpu takes interrupts
+pub fn enabled() -> bool {
+    let flags: usize;
+    unsafe {
+        asm!("pushf
+              pop     $0" : "=r"(flags) : : "memory" : "volatile");
+    }
+    flags & INTERRUPT_FLAG != 0
+}
+
+// interrupts are disabled while f runs and restored afterwards, so an irq
+// can't interrupt code that holds a lock its handler needs
+pub fn without_interrupts<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let enabled = enabled();
+    unsafe { irq::disable() };
+
+    let result = f();
+
+    if enabled {
+        unsafe { irq::enable() };
+    }
+    result
+}
+
+// enables interrupts and halts until one arrives, sti only takes effect
+// after hlt, so an irq can't slip in between a check made with interrupts
+// disabled and the halt
+pub fn wait() {
+    unsafe {
+        asm!("sti
+              hlt" : : : "memory" : "volatile");
+    }
+}
+
+// adds handler to line and unmasks it, lines can have any number of handlers
+pub fn register_irq(line: u8, handler: IrqHandler) {
+    assert!(
+        (line as usize) < LINES,
+        "irq line must be below {}, not {}",
+        LINES,
+        line,
+    );
+
+    without_interrupts(|| {
+        handlers().lock()[line as usize].push(handler);
+        unmask(line);
+    });
+}
+
+// goes to whichever controller routes the irqs
+fn unmask(line: u8) {
+    if apic::enabled() {
+        apic::unmask(line);
+    } else {
+        pic::unmask(line);
+    }
+}
+
+// called by the stubs, interrupts are disabled
+fn dispatch(line: u8) {
+    let apic = apic::enabled();
+    // the apic sends its spurious irqs to a vector of their own
+    if !apic && pic::spurious(line) {
+        return;
+    }
+
+    {
+        // handlers must not register irqs, the lock is held while they run
+        let handlers = handlers().lock();
+        for handler in handlers[line as usize].iter() {
+            handler();
+        }
+    }
+
+    if apic {
+        apic::eoi();
+    } else {
+        pic::eoi(line);
+    }
+}
+
+macro_rules! stubs {
+    ($($name:ident => $line:expr),*) => {
+        $(
+            unsafe extern "x86-interrupt" fn $name(
+                _stack_frame: &ExceptionStackFrame,
+            ) {
+                dispatch($line);
+            }
+        )*
+
+        // the idt entries of the lines, vector pic::OFFSET + line
+        pub const STUBS: [InterruptHandler; LINES] = [$($name),*];
+    };
+}
+
+stubs!(
+    irq0 => 0,
+    irq1 => 1,
+    irq2 => 2,
+    irq3 => 3,
+    irq4 => 4,
+    irq5 => 5,
+    irq6 => 6,
+    irq7 => 7,
+    irq8 => 8,
+    irq9 => 9,
+    irq10 => 10,
+    irq11 => 11,
+    irq12 => 12,
+    irq13 => 13,
+    irq14 => 14,
+    irq15 => 15
+);
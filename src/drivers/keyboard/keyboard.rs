@@ -1,11 +1,17 @@
 use port::Port;
+use drivers::pit;
 
 use super::*;
 
 #[allow(dead_code)] // most commands aren't used
 mod consts {
     pub const PORT: u16 = 0x60;
+    pub const STATUS: u16 = 0x64;
     pub const ATTEMPT_COUNT: usize = 3;
+    // how long a response may take, in milliseconds
+    pub const TIMEOUT: usize = 10;
+
+    pub const OUTPUT_FULL: u8 = 0b1;
 
     pub const ECHO: u8 = 0xee;
     pub const LED: u8 = 0xed;
@@ -163,6 +169,18 @@ pub struct Keyboard<'a> {
     modifier: Mod,
 }
 
+// the byte the keyboard answered with, None if it took longer than TIMEOUT
+fn response(port: &mut Port) -> Option<u8> {
+    let mut status = unsafe { Port::new(STATUS) };
+    for _ in 0..TIMEOUT * 10 {
+        if status.read_byte() & OUTPUT_FULL != 0 {
+            return Some(port.read_byte());
+        }
+        pit::delay(100);
+    }
+    None
+}
+
 impl<'a> Keyboard<'a> {
     pub fn new(
         repeat: u8,
@@ -243,7 +261,7 @@ impl<'a> Keyboard<'a> {
             let (com, arg) = com.into_bytes();
             port.write_byte(com);
             arg.map(|arg| port.write_byte(arg));
-            let resp = Response::from_byte(port.read_byte());
+            let resp = response(port).and_then(Response::from_byte);
             match resp {
                 Some(resp @ Response::Ack) => Ok(resp),
                 Some(resp @ Response::Resend) => Ok(resp),
@@ -1,8 +1,7 @@
 use spin::{Mutex, Once};
 use x86::shared::irq;
 
-use drivers::pic;
-use arch::interrupt::ExceptionStackFrame;
+use drivers::irq::{register_irq, wait};
 
 mod keyboard;
 mod scancode;
@@ -17,24 +16,28 @@ static mut KEYS: [bool; 256] = [false; 256];
 static mut INPUT: [Keycode; 256] = [Keycode::Unknown; 256];
 static KEYBOARD: Once<Option<Mutex<Keyboard<'static>>>> = Once::new();
 
-pub unsafe extern "x86-interrupt" fn handler(
-    _stack_frame: &ExceptionStackFrame,
-) {
+// the keyboard is on irq line 1
+const IRQ: u8 = 1;
+
+pub fn handler() {
     let key = Scancode::poll();
 
     try_handle()
         .and_then(|keyboard| keyboard.try_lock())
         .and_then(|mut keyboard| keyboard.input(key));
-
-    let mut pic = pic::try_handle().unwrap();
-    pic.0.eoi();
-    pic.1.eoi();
 }
 
 pub fn init(delay: u8, repeat: u16, scanset: Scanset) -> Result<(), ()> {
     let keyboard = KEYBOARD.call_once(move || {
-        unsafe { Keyboard::new(delay, repeat, &mut KEYS, &mut INPUT, scanset) }
-            .map(|keyboard| Mutex::new(keyboard))
+        let keyboard = unsafe {
+            Keyboard::new(delay, repeat, &mut KEYS, &mut INPUT, scanset)
+        }.map(|keyboard| Mutex::new(keyboard));
+
+        // the handler ignores keys until KEYBOARD is set
+        if keyboard.is_some() {
+            register_irq(IRQ, handler);
+        }
+        keyboard
     });
 
     keyboard.as_ref().map(|_| ()).ok_or(())
@@ -44,6 +47,7 @@ fn try_handle() -> Option<&'static Mutex<Keyboard<'static>>> {
     KEYBOARD.try().and_then(|keyboard| keyboard.as_ref())
 }
 
+// halts until a key arrives, the keyboard irq wakes the cpu
 pub fn poll() -> Option<Keycode> {
     try_handle().map(|keyboard| loop {
         unsafe {
@@ -55,13 +59,14 @@ pub fn poll() -> Option<Keycode> {
             key.last()
         };
 
-        unsafe {
-            irq::enable();
+        if let Some(key) = result {
+            unsafe {
+                irq::enable();
+            }
+            break key;
         }
 
-        if result.is_some() {
-            break result.unwrap();
-        }
+        wait();
     })
 }
 
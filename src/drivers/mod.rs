@@ -1,3 +1,6 @@
 pub mod vga;
 pub mod pic;
+pub mod irq;
+pub mod apic;
+pub mod pit;
 pub mod keyboard;
@@ -8,6 +8,13 @@ pub enum Mode {
     M8086,
 }
 
+// icw4 bit that makes the pic end interrupts on its own, eoi isn't needed
+const AUTO_EOI: u8 = 0b10;
+
+// ocw3 commands selecting what the next read of the command port returns
+const READ_IRR: u8 = 0x0a;
+const READ_ISR: u8 = 0x0b;
+
 impl From<Mode> for u8 {
     fn from(mode: Mode) -> u8 {
         match mode {
@@ -20,13 +27,18 @@ impl From<Mode> for u8 {
 pub struct Pic {
     com: Port,
     dat: Port,
+    auto_eoi: bool,
 }
 
 impl Pic {
     pub unsafe fn new(port: u16) -> Pic {
         let port = Port::new(port);
         let (com, dat) = port.into_siblings();
-        Pic { com, dat }
+        Pic {
+            com,
+            dat,
+            auto_eoi: false,
+        }
     }
 
     pub fn begin_init(mut self) -> PicInit {
@@ -38,6 +50,23 @@ impl Pic {
         self.com.write_byte(0x20); // 0x20 = end of interrupt
     }
 
+    // whether the pic was initialised to end interrupts on its own
+    pub fn auto_eoi(&self) -> bool {
+        self.auto_eoi
+    }
+
+    // in-service register, the lines whose interrupts are being handled
+    pub fn isr(&mut self) -> u8 {
+        self.com.write_byte(READ_ISR);
+        self.com.read_byte()
+    }
+
+    // interrupt request register, the lines raised but not yet delivered
+    pub fn irr(&mut self) -> u8 {
+        self.com.write_byte(READ_IRR);
+        self.com.read_byte()
+    }
+
     pub fn mask(&self) -> u8 {
         unsafe { self.dat.read_byte_unsafe() }
     }
@@ -91,4 +120,10 @@ impl PicInit {
     pub fn mode(&mut self, mode: Mode) {
         self.0.dat.write_byte(mode.into());
     }
+
+    // like mode, but the pic ends every interrupt as soon as it's delivered
+    pub fn mode_auto_eoi(&mut self, mode: Mode) {
+        self.0.dat.write_byte(u8::from(mode) | AUTO_EOI);
+        self.0.auto_eoi = true;
+    }
 }
@@ -1,46 +1,158 @@
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
 use spin::{Mutex, MutexGuard, Once};
 
+use arch::interrupt::ExceptionStackFrame;
+
 pub mod driver;
 
 pub use self::driver::{Mode, PIC1, PIC2, Pic};
 
 static PIC: Once<Mutex<(Pic, Pic)>> = Once::new();
 
-pub fn init() -> &'static Mutex<(Pic, Pic)> {
-    PIC.call_once(|| {
-        let (master, slave) = unsafe { (Pic::new(PIC1), Pic::new(PIC2)) };
+// irq lines 0 to 7 are at vectors OFFSET to OFFSET + 7, 8 to 15 right after
+pub const OFFSET: u8 = 0x20;
+// the last 16 vectors, where nothing else lives
+pub const DISABLED_OFFSET: u8 = 0xf0;
+
+// the master line the slave is connected to
+const CASCADE: u8 = 2;
+
+// a pic raises its lowest priority line when an irq goes away before it's
+// acknowledged, that's 7 on the master and 15 on the slave
+const SPURIOUS_LINE: u8 = 7;
+
+static SPURIOUS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// runs the init sequence, which clears the masks, so they're saved and
+// restored around it
+//
+// with auto_eoi, the pics end every irq as soon as it's delivered and eoi has
+// nothing left to do
+fn program(offset: u8, auto_eoi: bool) -> (Pic, Pic) {
+    let (master, slave) = unsafe { (Pic::new(PIC1), Pic::new(PIC2)) };
 
-        let master_mask = master.mask();
-        let slave_mask = slave.mask();
+    let master_mask = master.mask();
+    let slave_mask = slave.mask();
 
-        let mut master = master.begin_init();
-        let mut slave = slave.begin_init();
+    let mut master = master.begin_init();
+    let mut slave = slave.begin_init();
 
-        master.offset(0x20); // offset master irq's to 0x20:0x27
-        slave.offset(0x28); // offset slave irq's to 0x28:0x2f
+    master.offset(offset); // offset master irq's to offset:offset + 7
+    slave.offset(offset + 8); // offset slave irq's right after
 
-        master.slave(0b0100); // master has to know where its slave is,
-                              // i.e. where it receives irq from the slave
-        slave.identity(0b0010); // slave has to know its cascade identity,
-                                // i.e where it sends irqs to the master
+    master.slave(0b0100); // master has to know where its slave is,
+                          // i.e. where it receives irq from the slave
+    slave.identity(0b0010); // slave has to know its cascade identity,
+                            // i.e where it sends irqs to the master
 
+    if auto_eoi {
+        master.mode_auto_eoi(Mode::M8086); // 8086/88 mode
+        slave.mode_auto_eoi(Mode::M8086); // 8086/88 mode
+    } else {
         master.mode(Mode::M8086); // 8086/88 mode
         slave.mode(Mode::M8086); // 8086/88 mode
+    }
 
-        let mut master = master.end_init();
-        let mut slave = slave.end_init();
+    let mut master = master.end_init();
+    let mut slave = slave.end_init();
 
-        master.restore_mask(master_mask);
-        slave.restore_mask(slave_mask);
+    master.restore_mask(master_mask);
+    slave.restore_mask(slave_mask);
+
+    (master, slave)
+}
+
+pub fn init(auto_eoi: bool) -> &'static Mutex<(Pic, Pic)> {
+    PIC.call_once(|| Mutex::new(program(OFFSET, auto_eoi)))
+}
+
+// masks every line and moves the vectors to DISABLED_OFFSET, for when the
+// apic takes over, the pic can still raise spurious irqs there
+pub fn disable() {
+    let mut pic = handle();
+    let auto_eoi = pic.0.auto_eoi();
+    *pic = program(DISABLED_OFFSET, auto_eoi);
+    pic.0.set_all();
+    pic.1.set_all();
+}
 
-        Mutex::new((master, slave))
-    })
+// the idt handler of every vector of a disabled pic, anything arriving there
+// is spurious and needs no eoi
+pub unsafe extern "x86-interrupt" fn ignore(
+    _stack_frame: &ExceptionStackFrame,
+) {
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
 }
 
 pub fn handle() -> MutexGuard<'static, (Pic, Pic)> {
-    init().lock()
+    PIC.try().expect("pic used before pic::init").lock()
 }
 
 pub fn try_handle() -> Option<MutexGuard<'static, (Pic, Pic)>> {
     PIC.try().and_then(|pic| pic.try_lock())
 }
+
+// lines 8 to 15 are on the slave, which also needs the cascade line unmasked
+pub fn unmask(line: u8) {
+    let mut pic = handle();
+    if line < 8 {
+        pic.0.clear_mask(line);
+    } else {
+        pic.0.clear_mask(CASCADE);
+        pic.1.clear_mask(line - 8);
+    }
+}
+
+pub fn mask(line: u8) {
+    let mut pic = handle();
+    if line < 8 {
+        pic.0.set_mask(line);
+    } else {
+        pic.1.set_mask(line - 8);
+    }
+}
+
+// the slave only needs an eoi for its own lines, the master always does
+pub fn eoi(line: u8) {
+    let mut pic = handle();
+    if line >= 8 && !pic.1.auto_eoi() {
+        pic.1.eoi();
+    }
+    if !pic.0.auto_eoi() {
+        pic.0.eoi();
+    }
+}
+
+// true if an irq on line 7 or 15 was spurious, it must not be handled or get
+// an eoi then
+//
+// a spurious irq 15 is a real one for the master, which gets its eoi here
+//
+// in auto-eoi mode the in-service bit is already clear when the handler runs,
+// so spurious irqs can't be detected
+pub fn spurious(line: u8) -> bool {
+    if line & 0b111 != SPURIOUS_LINE {
+        return false;
+    }
+
+    let mut pic = handle();
+    let spurious = if line < 8 {
+        !pic.0.auto_eoi() && pic.0.isr() & 1 << SPURIOUS_LINE == 0
+    } else {
+        !pic.1.auto_eoi() && pic.1.isr() & 1 << SPURIOUS_LINE == 0
+    };
+
+    if spurious {
+        if line >= 8 && !pic.0.auto_eoi() {
+            pic.0.eoi();
+        }
+        SPURIOUS.fetch_add(1, Ordering::Relaxed);
+    }
+    spurious
+}
+
+// how many spurious irqs were ignored since boot
+pub fn spurious_count() -> usize {
+    SPURIOUS.load(Ordering::Relaxed)
+}
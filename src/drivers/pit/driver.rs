@@ -0,0 +1,90 @@
+use port::Port;
+
+// the channels count down at this rate, in Hz
+pub const OSCILLATOR: u32 = 1193182;
+
+const CHANNEL0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+// port b of the keyboard controller holds channel 2's gate and output
+const PORT_B: u16 = 0x61;
+
+// command bits, the count is written low byte first
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+
+// port b bits
+const GATE: u8 = 0b1;
+const SPEAKER: u8 = 0b10;
+const OUTPUT: u8 = 0b100000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    // counts down once, the output goes high at zero
+    OneShot,
+    // pulses the output whenever the count runs out and starts over
+    RateGenerator,
+}
+
+impl From<Mode> for u8 {
+    fn from(mode: Mode) -> u8 {
+        match mode {
+            Mode::OneShot => 0b000 << 1,
+            Mode::RateGenerator => 0b010 << 1,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Channel {
+    num: u8,
+    com: Port,
+    dat: Port,
+}
+
+impl Channel {
+    // num is 0, 1 or 2, 1 used to refresh dram and is better left alone
+    pub unsafe fn new(num: u8) -> Channel {
+        assert!(num < 3, "the pit has channels 0 to 2, not {}", num);
+        Channel {
+            num,
+            com: Port::new(COMMAND),
+            dat: Port::new(CHANNEL0 + num as u16),
+        }
+    }
+
+    // a count of 0 stands for 65536, the rate generator needs at least 2
+    pub fn program(&mut self, mode: Mode, count: u16) {
+        self.com
+            .write_byte(self.num << 6 | ACCESS_LOW_HIGH | u8::from(mode));
+        self.dat.write_byte(count as u8);
+        self.dat.write_byte((count >> 8) as u8);
+    }
+}
+
+// channel 2's gate and output, it only counts while the gate is open and its
+// output isn't wired to an irq, only to the speaker, which stays off
+#[derive(Debug, PartialEq, Eq)]
+pub struct Gate {
+    port: Port,
+}
+
+impl Gate {
+    pub unsafe fn new() -> Gate {
+        Gate {
+            port: Port::new(PORT_B),
+        }
+    }
+
+    pub fn open(&mut self) {
+        let bits = self.port.read_byte() & !SPEAKER;
+        self.port.write_byte(bits | GATE);
+    }
+
+    pub fn close(&mut self) {
+        let bits = self.port.read_byte() & !(SPEAKER | GATE);
+        self.port.write_byte(bits);
+    }
+
+    pub fn output(&mut self) -> bool {
+        self.port.read_byte() & OUTPUT != 0
+    }
+}
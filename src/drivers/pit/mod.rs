@@ -0,0 +1,113 @@
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use spin::{Mutex, Once};
+
+use drivers::irq::{self, register_irq};
+use smp;
+
+pub mod driver;
+
+pub use self::driver::{Channel, Gate, Mode, OSCILLATOR};
+
+// channel 0 is wired to irq line 0
+const IRQ: u8 = 0;
+
+// the most a channel counts down at once, ~54.9 ms
+const MAX_COUNT: u64 = 0x10000;
+
+// timer irqs since init, wraps after ~49 days at 1000 Hz on x86
+static TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+// what channel 0 actually runs at
+static FREQUENCY: Once<u32> = Once::new();
+// only delay uses channel 2, channel 0 is only programmed by init
+static CHANNEL2: Once<Mutex<(Channel, Gate)>> = Once::new();
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// programs channel 0 to raise irq 0 about hz times a second and returns how
+// often it really does, the divisor only gets so close
+//
+// only the first call programs the timer
+pub fn init(hz: u32) -> u32 {
+    *FREQUENCY.call_once(|| {
+        let divisor = (OSCILLATOR / hz.max(1)).max(2).min(MAX_COUNT as u32);
+        let mut channel = unsafe { Channel::new(0) };
+        // 65536 is written as 0
+        channel.program(Mode::RateGenerator, divisor as u16);
+        register_irq(IRQ, tick);
+        OSCILLATOR / divisor
+    })
+}
+
+// None before init
+pub fn frequency() -> Option<u32> {
+    FREQUENCY.try().cloned()
+}
+
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// milliseconds since init, 0 before it
+pub fn uptime() -> u64 {
+    frequency().map_or(0, |hz| ticks() as u64 * 1000 / hz as u64)
+}
+
+fn channel2() -> &'static Mutex<(Channel, Gate)> {
+    CHANNEL2.call_once(|| unsafe { Mutex::new((Channel::new(2), Gate::new())) })
+}
+
+// busy-waits at least us microseconds by counting down channel 2, which
+// works without init and with interrupts disabled
+//
+// irq handlers must not use it, the code they interrupted may hold channel 2
+pub fn delay(us: usize) {
+    let mut counts = (us as u64 * OSCILLATOR as u64 + 999999) / 1000000;
+
+    let mut channel2 = channel2().lock();
+    let (ref mut channel, ref mut gate) = *channel2;
+    while counts > 0 {
+        let count = counts.min(MAX_COUNT);
+        gate.close();
+        // 65536 is written as 0
+        channel.program(Mode::OneShot, count as u16);
+        gate.open();
+        while !gate.output() {}
+        counts -= count;
+    }
+}
+
+// how far read advances in us microseconds timed with delay, e.g. read can
+// be the time stamp counter or the local apic timer's current count
+pub fn calibrate<F>(us: usize, mut read: F) -> u64
+where
+    F: FnMut() -> u64,
+{
+    let start = read();
+    delay(us);
+    read().wrapping_sub(start)
+}
+
+// blocks at least ms milliseconds, halting between timer irqs
+//
+// the irqs only reach the boot cpu and only with interrupts enabled, other
+// cpus, disabled interrupts or a timer without init busy-wait with delay
+pub fn sleep(ms: usize) {
+    // before smp::init, only the boot cpu runs, after it only an application
+    // processor can be without its block
+    let boot_cpu = smp::current()
+        .map_or(smp::count() == 0, |percpu| percpu.id == 0);
+    let hz = match frequency() {
+        Some(hz) if boot_cpu && irq::enabled() => hz as u64,
+        _ => return delay(ms * 1000),
+    };
+
+    // the current tick is already partly over, it doesn't count
+    let count = ((ms as u64 * hz + 999) / 1000) as usize + 1;
+    let start = ticks();
+    while ticks().wrapping_sub(start) < count {
+        irq::wait();
+    }
+}
@@ -2,11 +2,13 @@ use core::ptr::Unique;
 
 use spin::{Mutex, MutexGuard, Once};
 
+use arch::kernel::KERNEL_BASE;
+
 pub mod driver;
 
 pub use self::driver::Vga;
 
-const VGA_BASE: usize = 0xe00b8000;
+const VGA_BASE: usize = KERNEL_BASE + 0xb8000;
 static VGA: Once<Mutex<Vga>> = Once::new();
 
 pub fn init() -> &'static Mutex<Vga> {
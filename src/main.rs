@@ -37,28 +37,42 @@ use x86::shared::irq;
 
 pub mod macros;
 #[cfg_attr(target_arch = "x86", path = "arch/x86/mod.rs")]
+#[cfg_attr(target_arch = "x86_64", path = "arch/x86_64/mod.rs")]
 pub mod arch;
 pub mod panic;
 pub mod mem;
 pub mod port;
 pub mod drivers;
 pub mod syscall;
+pub mod acpi;
+pub mod power;
+pub mod smp;
 
 #[path = "arch/x86/mod.rs"]
 #[cfg(rustfmt)]
 pub mod arch_x86;
+#[path = "arch/x86_64/mod.rs"]
+#[cfg(rustfmt)]
+pub mod arch_x86_64;
 
 use arch::Kinfo;
 use drivers::vga;
 use drivers::pic;
+use drivers::apic;
+use drivers::pit;
 use macros::*;
 
 // global_allocator doesn't work in modules
 // tracking issue: #27389
 // issue: #44113
-use linked_list_allocator::LockedHeap;
+use mem::heap::GrowableHeap;
+use mem::slab::SlabAllocator;
+pub static ALLOCATOR: GrowableHeap = GrowableHeap::empty();
 #[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static SLAB: SlabAllocator = SlabAllocator::new();
+
+// how often the timer ticks, in Hz
+const TIMER_FREQUENCY: u32 = 1000;
 
 pub fn kmain(kinfo: &Kinfo) {
     kprint!("paging... ");
@@ -84,6 +98,33 @@ pub fn kmain(kinfo: &Kinfo) {
         "heap size: {}kB",
         kinfo.heap_size() / 1024,
     );
+    kprintln!(
+        "heap usage: {}kB, peak: {}kB",
+        ALLOCATOR.used() / 1024,
+        ALLOCATOR.peak() / 1024,
+    );
+
+    kprint!("copy-on-write... ");
+    if mem::space::test_fork() {
+        kprintln!(
+            "{green}[OK]{reset}",
+            green = "\x1b[32m",
+            reset = "\x1b[0m"
+        );
+    } else {
+        panic!("[FAILED]");
+    }
+
+    kprint!("demand paging... ");
+    if mem::lazy::test_lazy() {
+        kprintln!(
+            "{green}[OK]{reset}",
+            green = "\x1b[32m",
+            reset = "\x1b[0m"
+        );
+    } else {
+        panic!("[FAILED]");
+    }
 
     kprint!("video graphics array driver... ");
 
@@ -101,7 +142,7 @@ pub fn kmain(kinfo: &Kinfo) {
     kprint!("programmable interrupt controller... ");
 
     {
-        pic::init();
+        pic::init(false);
         let mut pic = pic::handle();
         pic.0.set_all();
         pic.1.set_all();
@@ -113,6 +154,66 @@ pub fn kmain(kinfo: &Kinfo) {
         reset = "\x1b[0m"
     );
 
+    kprint!("advanced configuration and power interface... ");
+    if kinfo.acpi.is_some() {
+        kprintln!(
+            "{green}[OK]{reset}",
+            green = "\x1b[32m",
+            reset = "\x1b[0m"
+        );
+    } else {
+        kprintln!(
+            "{yellow}[SKIP]{reset}",
+            yellow = "\x1b[33m",
+            reset = "\x1b[0m"
+        );
+    }
+
+    power::init(kinfo);
+
+    kprint!("advanced programmable interrupt controller... ");
+    if apic::init(kinfo) {
+        kprintln!(
+            "{green}[OK]{reset}",
+            green = "\x1b[32m",
+            reset = "\x1b[0m"
+        );
+    } else {
+        kprintln!(
+            "{yellow}[SKIP]{reset}",
+            yellow = "\x1b[33m",
+            reset = "\x1b[0m"
+        );
+    }
+
+    // after the apic, irqs registered before it took over are lost
+    kprint!("programmable interval timer... ");
+    let frequency = pit::init(TIMER_FREQUENCY);
+    kprintln!(
+        "{green}[OK]{reset}",
+        green = "\x1b[32m",
+        reset = "\x1b[0m"
+    );
+    kprintln!("timer frequency: {}Hz", frequency);
+
+    // every cpu prints a line of its own as it comes up
+    let cpus = smp::init(kinfo);
+    kprint!("symmetric multiprocessing... ");
+    if cpus > 1 {
+        kprintln!(
+            "{green}[OK]{reset}",
+            green = "\x1b[32m",
+            reset = "\x1b[0m"
+        );
+    } else {
+        kprintln!(
+            "{yellow}[SKIP]{reset}",
+            yellow = "\x1b[33m",
+            reset = "\x1b[0m"
+        );
+    }
+    kprintln!("cpus: {}", cpus);
+
     kprint!("cpuid... ");
     if kinfo.cpuid.is_some() {
         kprintln!(
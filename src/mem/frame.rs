@@ -1,21 +1,67 @@
+use core::mem;
 use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
-use bit_field::{BitArray, BitField};
+use alloc::vec::Vec;
 
-use arch::paging::addr::Physical;
+use bit_field::BitField;
+
+use arch::kernel;
+use arch::paging::addr::{PhysAddr, Physical};
 
 #[cfg(target_pointer_width = "32")]
 const USIZE_BITS: usize = 32;
+#[cfg(target_pointer_width = "64")]
+const USIZE_BITS: usize = 64;
 
-const FRAMES: usize = 1024;
+// enough frames to cover 64 GiB of physical memory, or all 4 GiB that classic
+// paging reaches, everything above is never handed out
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+const FRAMES: usize = 0x1000000;
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+const FRAMES: usize = 0x100000;
 const LEN: usize = FRAMES / USIZE_BITS;
 
-pub const FRAME_SIZE: usize = 0x400000;
+// the frames below 4 GiB, where 32-bit registers like cr3 can point
+const LOW_FRAMES: usize = 0x100000;
+
+pub const FRAME_SIZE: usize = 0x1000;
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+pub const HUGE_FRAME_SIZE: usize = 0x400000;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+pub const HUGE_FRAME_SIZE: usize = 0x200000;
+
+// blocks of 2^MAX_ORDER frames are as big as a classic huge frame
+pub const MAX_ORDER: usize = 10;
+const ORDERS: usize = MAX_ORDER + 1;
+
+// one bitmap per order, each half as long as the previous one, up to 4 MiB in
+// total is way too much for the boot stack
+static mut BITMAP: [usize; 2 * LEN] = [0; 2 * LEN];
+
+// frames owned by a live handle, leaked frames are not counted
+static OUTSTANDING: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// index of the first word of the bitmap of order
+#[inline]
+fn offset(order: usize) -> usize {
+    2 * (LEN - (LEN >> order))
+}
+
+#[inline]
+fn frame_addr(frame: usize) -> Physical {
+    Physical::new((frame as PhysAddr) << 12)
+}
+
+#[inline]
+fn frame_number(addr: Physical) -> usize {
+    (addr.into_inner() >> 12) as usize
+}
 
 pub fn frames(inner: Range<usize>) -> Frames {
     let inner = Range {
-        start: inner.start >> 22,
-        end: (inner.end >> 22) + 1,
+        start: inner.start >> 12,
+        end: (inner.end >> 12) + 1,
     };
     Frames { inner }
 }
@@ -30,78 +76,396 @@ impl Iterator for Frames {
     type Item = Physical;
 
     fn next(&mut self) -> Option<Physical> {
-        self.inner.next().map(|frame| Physical::new(frame << 22))
+        self.inner.next().map(frame_addr)
     }
 }
 
+// an owned block of 2^order frames, it returns itself to the kernel's frame
+// allocator when dropped
+//
+// dropping a frame locks the frame allocator, so it must not happen while the
+// allocator is already locked, leak frames that are mapped permanently
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Frame {
     addr: Physical,
+    order: usize,
 }
 
 impl Frame {
+    fn new(addr: Physical, order: usize) -> Frame {
+        OUTSTANDING.fetch_add(1 << order, Ordering::Relaxed);
+        Frame { addr, order }
+    }
+
+    // takes back ownership of a leaked frame
+    pub unsafe fn from_raw(addr: Physical, order: usize) -> Frame {
+        Frame::new(addr, order)
+    }
+
     pub fn addr(&self) -> &Physical {
         &self.addr
     }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn size(&self) -> usize {
+        FRAME_SIZE << self.order
+    }
+
+    // gives up ownership without freeing the frame, e.g. for permanent kernel
+    // mappings
+    pub fn leak(self) -> Physical {
+        let addr = self.addr;
+        OUTSTANDING.fetch_sub(1 << self.order, Ordering::Relaxed);
+        mem::forget(self);
+        addr
+    }
 }
 
+impl Drop for Frame {
+    fn drop(&mut self) {
+        let addr = self.addr;
+        let order = self.order;
+        OUTSTANDING.fetch_sub(1 << order, Ordering::Relaxed);
+        unsafe { kernel::frame_alloc().free_raw(addr, order) }
+    }
+}
+
+// the counts of shared frames live on the heap, which comes up after the
+// frame allocator
+pub fn init_counts() {
+    let end = unsafe { kernel::frame_alloc() }.end;
+    let counts = vec![0; end];
+    unsafe { kernel::frame_alloc() }.counts = counts;
+}
+
+// the count of frames owned by live handles
+pub fn outstanding() -> usize {
+    OUTSTANDING.load(Ordering::Relaxed)
+}
+
+// checks in debug mode that every frame allocated during its lifetime has been
+// returned or leaked by the time it is dropped
+pub struct LeakCheck {
+    outstanding: usize,
+}
+
+impl LeakCheck {
+    pub fn new() -> LeakCheck {
+        LeakCheck {
+            outstanding: outstanding(),
+        }
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let outstanding = outstanding();
+        debug_assert!(
+            outstanding <= self.outstanding,
+            "{} frames were never returned",
+            outstanding - self.outstanding,
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stats {
+    pub free_blocks: [usize; ORDERS],
+    pub free_frames: usize,
+    pub used_frames: usize,
+}
+
+impl Stats {
+    pub fn largest_order(&self) -> Option<usize> {
+        (0..ORDERS).rev().find(|&order| self.free_blocks[order] > 0)
+    }
+
+    // percentage of free memory that can't satisfy an allocation of order
+    pub fn fragmentation(&self, order: usize) -> usize {
+        if self.free_frames == 0 {
+            return 0;
+        }
+
+        let usable: usize = (order..ORDERS)
+            .map(|order| self.free_blocks[order] << order)
+            .sum();
+        100 - usable * 100 / self.free_frames
+    }
+}
+
+// a binary buddy allocator
+//
+// a block of order n is 2^n frames big and aligned to its size, its buddy is
+// the other half of the block of order n + 1 that contains it
 pub struct Allocator {
-    // an array of 1024 bits
-    // on x86 it's 32 bytes long, so no worries about size
-    bitmap: [usize; LEN],
-    range: Range<usize>, // frame blocks, not frames or physical memory
+    // a bit per block of every order, set if the whole block is free and
+    // hasn't been merged with its buddy
+    bitmap: &'static mut [usize; 2 * LEN],
+    free: [usize; ORDERS],
+    // no free block of an order lies below its hint
+    hint: [usize; ORDERS],
+    total: usize,
+    // one past the highest frame that was ever added
+    end: usize,
+    // the owners of a frame besides the first one, for frames that address
+    // spaces share, empty until init_counts
+    counts: Vec<u16>,
 }
 
 impl Allocator {
-    pub fn with_range(range: Range<usize>) -> Allocator {
-        let mut bitmap = [0xffffffff_usize; LEN];
+    // creates an allocator without any frames, they have to be added with
+    // add_area
+    pub fn new() -> Allocator {
+        assert_has_not_been_called!(
+            "there can only be one frame allocator, it owns the bitmap"
+        );
+
+        let bitmap = unsafe { &mut BITMAP };
+        for word in bitmap.iter_mut() {
+            *word = 0;
+        }
+
+        Allocator {
+            bitmap,
+            free: [0; ORDERS],
+            hint: [0; ORDERS],
+            total: 0,
+            end: 0,
+            counts: Vec::new(),
+        }
+    }
+
+    fn is_free(&self, order: usize, block: usize) -> bool {
+        let word = offset(order) + block / USIZE_BITS;
+        self.bitmap[word].get_bit(block % USIZE_BITS)
+    }
 
-        for (idx, _) in frames(range.clone()).enumerate() {
-            bitmap.set_bit(idx, false);
+    fn set_free(&mut self, order: usize, block: usize, free: bool) {
+        let word = block / USIZE_BITS;
+        self.bitmap[offset(order) + word].set_bit(block % USIZE_BITS, free);
+        if free {
+            self.free[order] += 1;
+            self.hint[order] = self.hint[order].min(word);
+        } else {
+            self.free[order] -= 1;
         }
+    }
+
+    fn find_free(&mut self, order: usize) -> Option<usize> {
+        if self.free[order] == 0 {
+            return None;
+        }
+
+        let base = offset(order);
+        for word in self.hint[order]..(LEN >> order) {
+            let bits = self.bitmap[base + word];
+            if bits != 0 {
+                self.hint[order] = word;
+                return Some(word * USIZE_BITS + bits.trailing_zeros() as usize);
+            }
+        }
+
+        unreachable!()
+    }
+
+    // the order of the free block that contains frame, if there is one
+    fn free_order(&self, frame: usize) -> Option<usize> {
+        (0..ORDERS).find(|&order| self.is_free(order, frame >> order))
+    }
 
-        let range = (range.start >> 27)..(range.end >> 27) + 1;
-        Allocator { bitmap, range }
+    // marks a block free and merges it with its buddy for as long as the buddy
+    // is free too
+    fn free_block(&mut self, block: usize, order: usize) {
+        let mut block = block;
+        let mut order = order;
+        while order < MAX_ORDER && self.is_free(order, block ^ 1) {
+            self.set_free(order, block ^ 1, false);
+            block >>= 1;
+            order += 1;
+        }
+        self.set_free(order, block, true);
+    }
+
+    // frees every frame that lies entirely inside area, the memory map's
+    // areas can be passed as they are
+    //
+    // areas must not overlap each other
+    pub fn add_area(&mut self, area: Range<u64>) {
+        let start = area.start.saturating_add(FRAME_SIZE as u64 - 1) >> 12;
+        let mut frame = start.min(FRAMES as u64) as usize;
+        let end = (area.end >> 12).min(FRAMES as u64) as usize;
+        self.end = self.end.max(end);
+
+        while frame < end {
+            // the biggest aligned block that still fits in the area
+            let mut order = MAX_ORDER;
+            while frame & ((1 << order) - 1) != 0
+                || frame + (1 << order) > end
+            {
+                order -= 1;
+            }
+
+            self.free_block(frame >> order, order);
+            self.total += 1 << order;
+            frame += 1 << order;
+        }
+    }
+
+    // marks every frame that overlaps range as used, so it's never handed out
+    pub fn reserve(&mut self, range: Range<usize>) {
+        let start = range.start >> 12;
+        let end = range.end.saturating_add(FRAME_SIZE - 1) >> 12;
+        let end = end.min(FRAMES);
+
+        for frame in start..end {
+            let order = match self.free_order(frame) {
+                Some(order) => order,
+                None => continue,
+            };
+
+            // split the free block, keeping every half without frame free
+            self.set_free(order, frame >> order, false);
+            for order in (0..order).rev() {
+                self.set_free(order, (frame >> order) ^ 1, true);
+            }
+            self.total -= 1;
+        }
     }
 
     pub fn allocate(&mut self) -> Option<Frame> {
-        for idx in self.range.clone() {
-            if self.bitmap[idx] != !0 {
-                let word = self.bitmap[idx];
-                for bit in 0..USIZE_BITS {
-                    if !word.get_bit(bit) {
-                        let frame = idx << 5 | bit;
-                        self.bitmap.set_bit(frame, true);
-                        let addr = frame << 22;
-                        let frame = Frame {
-                            addr: Physical::new(addr),
-                        };
-                        return Some(frame);
-                    }
+        self.allocate_order(0)
+    }
+
+    pub fn deallocate(&mut self, frame: Frame) {
+        let order = frame.order;
+        self.deallocate_order(frame, order)
+    }
+
+    // allocates 2^order physically contiguous frames, aligned to their size
+    pub fn allocate_order(&mut self, order: usize) -> Option<Frame> {
+        self.allocate_below(order, FRAMES)
+    }
+
+    // like allocate_order, but the frames lie below 4 GiB
+    pub fn allocate_low(&mut self, order: usize) -> Option<Frame> {
+        self.allocate_below(order, LOW_FRAMES)
+    }
+
+    // find_free hands out the lowest block of an order, so if that one isn't
+    // below end, no block of the order is
+    fn allocate_below(&mut self, order: usize, end: usize) -> Option<Frame> {
+        assert!(
+            order <= MAX_ORDER,
+            "order must be at most {}, is {}",
+            MAX_ORDER,
+            order,
+        );
+
+        let mut found = None;
+        for current in order..ORDERS {
+            match self.find_free(current) {
+                Some(block) if (block + 1) << current <= end => {
+                    found = Some((block, current));
+                    break;
                 }
+                _ => {}
             }
         }
+        let (mut block, mut current) = found?;
 
-        None
+        // split the block, handing the upper halves back
+        self.set_free(current, block, false);
+        while current > order {
+            current -= 1;
+            block <<= 1;
+            self.set_free(current, block | 1, true);
+        }
+
+        Some(Frame::new(frame_addr(block << order), order))
     }
 
-    pub fn deallocate(&mut self, frame: Frame) {
-        let idx = frame.addr.into_inner() >> 22;
-        self.bitmap.set_bit(idx, false);
+    pub fn deallocate_order(&mut self, frame: Frame, order: usize) {
+        assert!(
+            frame.order == order,
+            "frame {:x} is of order {}, not {}",
+            frame.addr,
+            frame.order,
+            order,
+        );
+        let addr = frame.leak();
+        self.free_raw(addr, order);
     }
 
-    // returns the count of free frames
-    pub fn free(&self) -> usize {
-        let mut count = 0;
+    fn free_raw(&mut self, addr: Physical, order: usize) {
+        let frame = frame_number(addr);
+        assert!(
+            frame & ((1 << order) - 1) == 0,
+            "frame {:x} is not aligned to order {}",
+            addr,
+            order,
+        );
+
+        if cfg!(debug_assertions) {
+            for frame in frame..frame + (1 << order) {
+                assert!(
+                    self.free_order(frame).is_none(),
+                    "double free of frame {:x}",
+                    frame_addr(frame),
+                );
+            }
+        }
+
+        self.free_block(frame >> order, order);
+    }
+
+    // adds an owner to a frame
+    pub fn share(&mut self, addr: Physical) {
+        let frame = frame_number(addr);
+        assert!(
+            frame < self.counts.len(),
+            "frame {:x} can't be shared before init_counts",
+            addr,
+        );
+        self.counts[frame] = self.counts[frame]
+            .checked_add(1)
+            .unwrap_or_else(|| panic!("frame {:x} has too many owners", addr));
+    }
+
+    // the count of owners besides the first one
+    pub fn shares(&self, addr: Physical) -> usize {
+        let frame = frame_number(addr);
+        self.counts.get(frame).map_or(0, |&count| count as usize)
+    }
 
-        for idx in 0..LEN {
-            count += self.bitmap[idx].count_zeros() as usize;
+    // drops an owner of a frame, which is freed along with the last one,
+    // returns whether it was
+    pub fn release(&mut self, addr: Physical) -> bool {
+        if self.shares(addr) > 0 {
+            self.counts[frame_number(addr)] -= 1;
+            false
+        } else {
+            self.free_raw(addr, 0);
+            true
         }
+    }
 
-        count
+    // returns the count of free frames
+    pub fn free(&self) -> usize {
+        (0..ORDERS).map(|order| self.free[order] << order).sum()
     }
 
     pub fn used(&self) -> usize {
-        self.range.end - self.range.start - self.free()
+        self.total - self.free()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            free_blocks: self.free,
+            free_frames: self.free(),
+            used_frames: self.used(),
+        }
     }
 }
@@ -0,0 +1,138 @@
+use core::mem;
+
+use alloc::allocator::{Alloc, AllocErr, Layout};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use arch::kernel;
+use arch::paging::addr::Virtual;
+use mem::page::PAGE_SIZE;
+
+struct Inner {
+    heap: Heap,
+    used: usize,
+    peak: usize,
+}
+
+// a linked list heap that maps more pages at its top whenever it runs out of
+// memory
+pub struct GrowableHeap {
+    inner: Mutex<Inner>,
+}
+
+impl GrowableHeap {
+    pub const fn empty() -> GrowableHeap {
+        GrowableHeap {
+            inner: Mutex::new(Inner {
+                heap: Heap::empty(),
+                used: 0,
+                peak: 0,
+            }),
+        }
+    }
+
+    // the whole range has to be mapped already
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        self.inner.lock().heap.init(heap_start, heap_size);
+    }
+
+    // bytes currently allocated
+    pub fn used(&self) -> usize {
+        self.inner.lock().used
+    }
+
+    // the most bytes that were ever allocated at once
+    pub fn peak(&self) -> usize {
+        self.inner.lock().peak
+    }
+
+    // bytes mapped for the heap, allocated or not
+    pub fn size(&self) -> usize {
+        self.inner.lock().heap.size()
+    }
+}
+
+// maps enough pages at the top of the heap to satisfy layout, returns whether
+// the heap grew at all
+//
+// every lock is only tried, the allocation fails instead of deadlocking when
+// the heap is used while one of them is held
+fn grow(heap: &mut Heap, layout: &Layout) -> bool {
+    // room for alignment and the hole list's bookkeeping
+    let slack = layout.align() + 2 * mem::size_of::<usize>();
+    let pages = (layout.size() + slack + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    let mut table = match kernel::try_page_table() {
+        Some(table) => table,
+        None => return false,
+    };
+    let mut page_alloc = match kernel::try_page_alloc() {
+        Some(page_alloc) => page_alloc,
+        None => return false,
+    };
+    let mut frame_alloc = match kernel::try_frame_alloc() {
+        Some(frame_alloc) => frame_alloc,
+        None => return false,
+    };
+
+    let top = heap.bottom() + heap.size();
+    let mut grown = 0;
+    for i in 0..pages {
+        let addr = top + i * PAGE_SIZE;
+        let page = match page_alloc.allocate_at(Virtual::new(addr)) {
+            Some(page) => page,
+            None => break,
+        };
+        // something else lives above the heap
+        if *page.addr() != addr {
+            page_alloc.deallocate(page);
+            break;
+        }
+        let frame = match frame_alloc.allocate() {
+            Some(frame) => frame,
+            None => {
+                page_alloc.deallocate(page);
+                break;
+            }
+        };
+
+        // the heap never shrinks, so its pages are never freed
+        table
+            .global_map_page(page.leak(), frame.leak(), &mut frame_alloc)
+            .flush();
+        grown += PAGE_SIZE;
+    }
+
+    if grown > 0 {
+        unsafe {
+            heap.extend(grown);
+        }
+    }
+
+    grown > 0
+}
+
+unsafe impl<'a> Alloc for &'a GrowableHeap {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let mut inner = self.inner.lock();
+
+        let mut result = inner.heap.allocate_first_fit(layout.clone());
+        if result.is_err() && grow(&mut inner.heap, &layout) {
+            result = inner.heap.allocate_first_fit(layout.clone());
+        }
+
+        if result.is_ok() {
+            inner.used += layout.size();
+            inner.peak = inner.peak.max(inner.used);
+        }
+
+        result
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut inner = self.inner.lock();
+        inner.used -= layout.size();
+        inner.heap.deallocate(ptr, layout);
+    }
+}
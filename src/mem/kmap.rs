@@ -0,0 +1,152 @@
+use core::mem;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use arch::kernel::{self, KMAP_BASE};
+use arch::paging::addr::*;
+use arch::paging::table::{ActiveTable, Entry, EntryBuilder, PageSize};
+use mem::page::PAGE_SIZE;
+
+// how many frames can be mapped at once
+pub const SLOTS: usize = 16;
+
+// a bit per slot, set while the slot holds a mapping
+static USED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// reserves the pages of the slots and creates their page table, so mapping a
+// slot never needs to allocate
+pub fn init() {
+    assert_has_not_been_called!("the kmap slots can only be reserved once");
+
+    let mut table = unsafe { kernel::page_table() };
+    let mut pages = unsafe { kernel::page_alloc() };
+    let mut frames = unsafe { kernel::frame_alloc() };
+
+    for slot in 0..SLOTS {
+        let addr = KMAP_BASE + slot * PAGE_SIZE;
+        let page = pages.allocate_at(Virtual::new(addr));
+        assert!(
+            page.as_ref().map_or(false, |page| *page.addr() == addr),
+            "kmap slot {} at {:08x} is already in use",
+            slot,
+            addr,
+        );
+        // the slots are never given back
+        page.unwrap().leak();
+    }
+
+    // the page table stays when the page is unmapped again
+    let first = Virtual::new(KMAP_BASE);
+    table
+        .default_map_page(first, Physical::new(0), &mut frames)
+        .flush();
+    table.unmap(first).flush();
+}
+
+fn claim() -> Option<usize> {
+    loop {
+        let used = USED.load(Ordering::SeqCst);
+        let slot = (!used).trailing_zeros() as usize;
+        if slot >= SLOTS {
+            return None;
+        }
+        let claimed = used | 1 << slot;
+        if USED.compare_and_swap(used, claimed, Ordering::SeqCst) == used {
+            return Some(slot);
+        }
+    }
+}
+
+fn release(slot: usize) {
+    USED.fetch_and(!(1 << slot), Ordering::SeqCst);
+}
+
+fn map(table: &mut ActiveTable, slot: usize, phys: Physical) {
+    let virt = Virtual::new(KMAP_BASE + slot * PAGE_SIZE);
+    let entry = EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .read_write()
+        .no_execute()
+        .page_size(PageSize::Normal)
+        .build();
+    table
+        .remap_page(virt, entry)
+        .unwrap_or_else(|| panic!("kmap used before kmap::init"))
+        .flush();
+}
+
+// maps a frame into a free slot, None if every slot is taken
+pub fn kmap(phys: Physical) -> Option<KMap> {
+    assert!(
+        phys.into_inner() & (PAGE_SIZE - 1) as PhysAddr == 0,
+        "kmap frame {:08x} must be page-aligned",
+        phys,
+    );
+
+    let slot = claim()?;
+    map(&mut unsafe { kernel::page_table() }, slot, phys);
+    Some(KMap { slot, phys })
+}
+
+// like kmap, but also None if the page table is locked, for interrupt and
+// fault handlers
+pub fn try_kmap(phys: Physical) -> Option<KMap> {
+    assert!(
+        phys.into_inner() & (PAGE_SIZE - 1) as PhysAddr == 0,
+        "kmap frame {:08x} must be page-aligned",
+        phys,
+    );
+
+    let slot = claim()?;
+    match kernel::try_page_table() {
+        Some(mut table) => map(&mut table, slot, phys),
+        None => {
+            release(slot);
+            return None;
+        }
+    }
+    Some(KMap { slot, phys })
+}
+
+// a frame mapped into a slot, it's unmapped and its slot freed when dropped
+//
+// dropping it locks the page table, so it must not happen while the table is
+// already locked
+#[derive(Debug)]
+pub struct KMap {
+    slot: usize,
+    phys: Physical,
+}
+
+impl KMap {
+    pub fn addr(&self) -> Virtual {
+        Virtual::new(KMAP_BASE + self.slot * PAGE_SIZE)
+    }
+
+    pub fn phys(&self) -> Physical {
+        self.phys
+    }
+
+    pub fn as_ptr<T>(&self) -> *mut T {
+        self.addr().into_inner() as *mut T
+    }
+
+    // the whole frame as a slice of T
+    pub unsafe fn as_slice<T>(&mut self) -> &mut [T] {
+        let len = PAGE_SIZE / mem::size_of::<T>();
+        slice::from_raw_parts_mut(self.as_ptr(), len)
+    }
+
+    // the whole frame as entries, for building page tables
+    pub fn entries(&mut self) -> &mut [Entry] {
+        unsafe { self.as_slice() }
+    }
+}
+
+impl Drop for KMap {
+    fn drop(&mut self) {
+        unsafe { kernel::page_table() }.unmap(self.addr()).flush();
+        release(self.slot);
+    }
+}
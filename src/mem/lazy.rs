@@ -0,0 +1,243 @@
+use core::ops::Range;
+use core::ptr;
+
+use alloc::vec::Vec;
+
+use spin::{Mutex, Once};
+
+use arch::kernel::{self, KERNEL_BASE};
+use arch::interrupt::exceptions::PageFaultError;
+use arch::paging::addr::*;
+use arch::paging::table::{self, Entry, EntryBuilder, Flags, PageSize};
+use arch::paging::tlb::Batch;
+use mem::frame::{Frame, LeakCheck};
+use mem::page::{pages, PAGE_SIZE};
+
+static REGIONS: Once<Mutex<Vec<Region>>> = Once::new();
+
+// a virtual range whose frames are only allocated and mapped when a page is
+// first touched
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Region {
+    range: Range<Virtual>,
+    flags: Flags,
+    // the root of the address space whose user half the region is in, None in
+    // the kernel half, which every address space shares
+    root: Option<Physical>,
+}
+
+impl Region {
+    pub fn range(&self) -> &Range<Virtual> {
+        &self.range
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    pub fn contains(&self, addr: Virtual) -> bool {
+        self.range.start <= addr && addr < self.range.end
+    }
+
+    fn overlaps(&self, range: &Range<Virtual>) -> bool {
+        self.range.start < range.end && range.start < self.range.end
+    }
+
+    fn is_visible_from(&self, root: Physical) -> bool {
+        self.root.map_or(true, |own| own == root)
+    }
+}
+
+fn regions() -> &'static Mutex<Vec<Region>> {
+    REGIONS.call_once(|| Mutex::new(Vec::new()))
+}
+
+fn entry(phys: Physical, flags: Flags) -> Entry {
+    let mut builder = EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .page_size(PageSize::Normal);
+    if flags.contains(Flags::RW) {
+        builder = builder.read_write();
+    }
+    if flags.contains(Flags::USER) {
+        builder = builder.user();
+    }
+    if flags.contains(Flags::NO_EXECUTE) {
+        builder = builder.no_execute();
+    }
+    // released when it's unmapped or its address space is dropped
+    builder.flags(Flags::OWNED).build()
+}
+
+// makes range of the kernel half a lazy region, the caller has to own its
+// pages, only RW and NO_EXECUTE of flags are honoured
+//
+// lazy regions in the user half belong to an address space, see
+// AddressSpace::register_lazy
+pub fn register(range: Range<Virtual>, flags: Flags) {
+    assert!(
+        range.start >= KERNEL_BASE,
+        "lazy region {:08x}-{:08x} isn't in the kernel half",
+        range.start,
+        range.end,
+    );
+    insert(None, range, flags - Flags::USER);
+}
+
+pub(crate) fn insert(root: Option<Physical>, range: Range<Virtual>,
+                     flags: Flags) {
+    assert!(
+        range.start & (PAGE_SIZE - 1) == 0 && range.end & (PAGE_SIZE - 1) == 0,
+        "lazy region {:08x}-{:08x} must be page-aligned",
+        range.start,
+        range.end,
+    );
+
+    let mut regions = regions().lock();
+    assert!(
+        !regions
+            .iter()
+            .any(|region| region.root == root && region.overlaps(&range)),
+        "lazy region {:08x}-{:08x} overlaps another one",
+        range.start,
+        range.end,
+    );
+    regions.push(Region { range, flags, root });
+}
+
+pub(crate) fn remove(root: Option<Physical>, start: Virtual) -> Option<Region> {
+    let mut regions = regions().lock();
+    let idx = regions
+        .iter()
+        .position(|region| region.root == root && region.range.start == start);
+    idx.map(|idx| regions.remove(idx))
+}
+
+// gives the address space at child the same regions as the one at parent
+pub(crate) fn fork(parent: Physical, child: Physical) {
+    let mut regions = regions().lock();
+    let copies: Vec<Region> = regions
+        .iter()
+        .filter(|region| region.root == Some(parent))
+        .map(|region| Region { root: Some(child), ..region.clone() })
+        .collect();
+    regions.extend(copies);
+}
+
+// forgets the regions of the address space at root, their frames are the
+// space's to release
+pub(crate) fn forget(root: Physical) {
+    regions().lock().retain(|region| region.root != Some(root));
+}
+
+// forgets the region of the kernel half that starts at start and frees every
+// frame that was allocated for it
+pub fn unregister(start: Virtual) -> Option<Region> {
+    remove(None, start).map(|region| {
+        let mut table = unsafe { kernel::page_table() };
+        let mut batch = Batch::new();
+        let end = region.range.end.into_inner() - 1;
+        for page in pages(region.range.start.into_inner()..end) {
+            if let Some(entry) = batch.add(table.unmap(page)) {
+                let phys = entry.into_physical();
+                let _frame = unsafe { Frame::from_raw(phys, 0) };
+            }
+        }
+        batch.flush();
+        region
+    })
+}
+
+// called by the page-fault handler, returns whether the fault was resolved
+//
+// only the kernel's regions and those of the active address space count
+pub fn resolve(addr: Virtual, error: PageFaultError) -> bool {
+    let root = table::active_root();
+    let flags = REGIONS
+        .try()
+        .and_then(|regions| regions.try_lock())
+        .and_then(|regions| {
+            regions
+                .iter()
+                .find(|region| {
+                    region.contains(addr) && region.is_visible_from(root)
+                })
+                .map(|region| region.flags)
+        });
+    let flags = match flags {
+        Some(flags) => flags,
+        None => return false,
+    };
+
+    if error.contains(PageFaultError::WRITE) && !flags.contains(Flags::RW) {
+        return false;
+    }
+    if error.contains(PageFaultError::USER) && !flags.contains(Flags::USER) {
+        return false;
+    }
+
+    let (mut table, mut frames) =
+        match (kernel::try_page_table(), kernel::try_frame_alloc()) {
+            (Some(table), Some(frames)) => (table, frames),
+            _ => return false,
+        };
+
+    let frame = match frames.allocate() {
+        Some(frame) => frame.leak(),
+        None => return false,
+    };
+
+    // the page is writable until it has been cleared
+    let page = addr & !(PAGE_SIZE - 1);
+    table.map_page(page, entry(frame, Flags::RW), &mut frames).flush();
+    unsafe {
+        ptr::write_bytes(page.into_inner() as *mut u8, 0, PAGE_SIZE);
+    }
+    table.map_page(page, entry(frame, flags), &mut frames).flush();
+
+    true
+}
+
+// touches every other page of a region in the kernel half, the touched pages
+// have to read as zeroes and keep what's written to them, the others have to
+// stay unmapped, and unregister has to free a frame per touched page
+pub fn test_lazy() -> bool {
+    let _leaks = LeakCheck::new();
+    let count = 4;
+    let window = Virtual::new(KERNEL_BASE)..Virtual::new(kernel::MMIO_BASE);
+    let range = match unsafe { kernel::page_alloc() }
+        .allocate_range(count, window)
+    {
+        Some(range) => range,
+        None => return false,
+    };
+    register(range.clone(), Flags::RW);
+
+    let mut zeroed = true;
+    let mut kept = true;
+    let mut touched = 0;
+    for page in (0..count).filter(|page| page % 2 == 0) {
+        touched += 1;
+        let ptr = (range.start + page * PAGE_SIZE).into_inner() as *mut u32;
+        unsafe {
+            zeroed &= ptr::read_volatile(ptr) == 0;
+            ptr::write_volatile(ptr, page as u32 + 1);
+            kept &= ptr::read_volatile(ptr) == page as u32 + 1;
+        }
+    }
+
+    let mapped = {
+        let table = unsafe { kernel::page_table() };
+        (0..count).all(|page| {
+            table.is_used(range.start + page * PAGE_SIZE) == (page % 2 == 0)
+        })
+    };
+
+    let free = unsafe { kernel::frame_alloc() }.free();
+    unregister(range.start);
+    let freed = unsafe { kernel::frame_alloc() }.free() - free;
+    unsafe { kernel::page_alloc() }.deallocate_range(range);
+
+    zeroed && kept && mapped && freed == touched
+}
@@ -0,0 +1,58 @@
+use arch::kernel::{self, MMIO_BASE, STACKS_BASE};
+use arch::paging::addr::*;
+use arch::paging::table::{EntryBuilder, PageSize};
+use mem::page::{pages, PAGE_SIZE};
+
+// maps size bytes of device memory or firmware tables at phys uncached, None
+// if there are no pages left
+//
+// the pages come from the window at MMIO_BASE in the kernel half, so every
+// address space sees them, the address keeps phys's offset into its page
+pub fn map(phys: Physical, size: usize) -> Option<Virtual> {
+    let offset = phys.into_inner() as usize & (PAGE_SIZE - 1);
+    let start = Physical::new(phys.into_inner() - offset as PhysAddr);
+    let count = (offset + size + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    let window = Virtual::new(MMIO_BASE)..Virtual::new(STACKS_BASE);
+    let range =
+        unsafe { kernel::page_alloc() }.allocate_range(count, window)?;
+
+    let mut table = unsafe { kernel::page_table() };
+    let mut frames = unsafe { kernel::frame_alloc() };
+
+    let end = range.end.into_inner() - 1;
+    for (idx, page) in pages(range.start.into_inner()..end).enumerate() {
+        let entry = EntryBuilder::new()
+            .addr(start + idx * PAGE_SIZE)
+            .present()
+            .read_write()
+            .no_cache()
+            .write_through()
+            .global()
+            .no_execute()
+            .page_size(PageSize::Normal)
+            .build();
+        table.map_page(page, entry, &mut frames).flush();
+    }
+
+    Some(range.start + offset)
+}
+
+// takes down a mapping made by map, size as it was given to map
+pub fn unmap(addr: Virtual, size: usize) {
+    let offset = addr.into_inner() & (PAGE_SIZE - 1);
+    let start = addr.into_inner() - offset;
+    let count = (offset + size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let end = start + count * PAGE_SIZE;
+
+    {
+        let mut table = unsafe { kernel::page_table() };
+        for page in pages(start..end - 1) {
+            // the frames belong to the device or the firmware
+            table.unmap(page).flush();
+        }
+    }
+
+    unsafe { kernel::page_alloc() }
+        .deallocate_range(Virtual::new(start)..Virtual::new(end));
+}
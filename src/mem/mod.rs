@@ -1,2 +1,9 @@
 pub mod page;
 pub mod frame;
+pub mod lazy;
+pub mod kmap;
+pub mod mmio;
+pub mod space;
+pub mod stack;
+pub mod heap;
+pub mod slab;
@@ -1,22 +1,47 @@
+use core::mem;
 use core::ops::Range;
 
 use bit_field::{BitArray, BitField};
 
+use arch::kernel;
 use arch::paging::addr::Virtual;
 use arch::paging::table::ActiveTable;
 
 #[cfg(target_pointer_width = "32")]
 const USIZE_BITS: usize = 32;
+#[cfg(target_pointer_width = "64")]
+const USIZE_BITS: usize = 64;
 
-const PAGES: usize = 1024;
+// enough pages to cover 4 GiB of virtual memory starting at BASE, that's the
+// whole address space on x86 and the top of it, where the kernel lives, on
+// x86_64
+const PAGES: usize = 0x100000;
 const LEN: usize = PAGES / USIZE_BITS;
 
-pub const PAGE_SIZE: usize = 0x400000;
+#[cfg(target_pointer_width = "32")]
+const BASE: usize = 0;
+#[cfg(target_pointer_width = "64")]
+const BASE: usize = 0xffffffff00000000;
+
+pub const PAGE_SIZE: usize = 0x1000;
+#[cfg(not(any(feature = "pae", target_arch = "x86_64")))]
+pub const HUGE_PAGE_SIZE: usize = 0x400000;
+#[cfg(any(feature = "pae", target_arch = "x86_64"))]
+pub const HUGE_PAGE_SIZE: usize = 0x200000;
+
+// an array of 1M bits, 128kB is too much for the boot stack
+static mut BITMAP: [usize; LEN] = [0; LEN];
+
+// whether virt is one of the pages the allocator hands out
+pub fn managed(virt: Virtual) -> bool {
+    let addr = virt.into_inner();
+    addr >= BASE && (addr - BASE) / PAGE_SIZE < PAGES
+}
 
 pub fn pages(inner: Range<usize>) -> Pages {
     let inner = Range {
-        start: inner.start >> 22,
-        end: (inner.end >> 22) + 1,
+        start: inner.start >> 12,
+        end: (inner.end >> 12) + 1,
     };
     Pages { inner }
 }
@@ -31,55 +56,81 @@ impl Iterator for Pages {
     type Item = Virtual;
 
     fn next(&mut self) -> Option<Virtual> {
-        self.inner.next().map(|page| Virtual::new(page << 22))
+        self.inner.next().map(|page| Virtual::new(page << 12))
     }
 }
 
+// an owned page, it returns itself to the kernel's page allocator when
+// dropped
+//
+// dropping a page only frees its address, whatever is mapped there stays
+// mapped, and like frames it must not be dropped while the page allocator is
+// locked
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Page {
     addr: Virtual,
 }
 
 impl Page {
+    // takes back ownership of a leaked page
+    pub unsafe fn from_raw(addr: Virtual) -> Page {
+        Page { addr }
+    }
+
     pub fn addr(&self) -> &Virtual {
         &self.addr
     }
+
+    // gives up ownership without freeing the page, e.g. for permanent kernel
+    // mappings
+    pub fn leak(self) -> Virtual {
+        let addr = self.addr;
+        mem::forget(self);
+        addr
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        let addr = self.addr;
+        unsafe { kernel::page_alloc().free_raw(addr) }
+    }
 }
 
 pub struct Allocator {
-    // an array of 1024 bits
-    // on x86 it's 32 bytes long, so no worries about size
-    bitmap: [usize; LEN],
+    bitmap: &'static mut [usize; LEN],
 }
 
 impl Allocator {
     pub fn with_used<'a>(active: &'a ActiveTable<'a>) -> Allocator {
-        let mut bitmap = [0_usize; LEN];
+        assert_has_not_been_called!(
+            "there can only be one page allocator, it owns the bitmap"
+        );
 
-        for (idx, page) in pages(0..usize::max_value()).enumerate() {
-            if active.is_used(page) {
-                bitmap.set_bit(idx, true);
-            }
+        let bitmap = unsafe { &mut BITMAP };
+
+        for (idx, page) in pages(BASE..usize::max_value()).enumerate() {
+            bitmap.set_bit(idx, active.is_used(page));
         }
 
         Allocator { bitmap }
     }
 
     pub fn allocate(&mut self) -> Option<Page> {
-        self.allocate_at(Virtual::new(0))
+        self.allocate_at(Virtual::new(BASE))
     }
 
     pub fn allocate_at(&mut self, virt: Virtual) -> Option<Page> {
-        let idx = virt.into_inner() >> 27;
+        let idx = (virt.into_inner().max(BASE) - BASE) / PAGE_SIZE / USIZE_BITS;
 
         for idx in idx..LEN {
             if self.bitmap[idx] != !0 {
                 let word = self.bitmap[idx];
                 for bit in 0..USIZE_BITS {
                     if !word.get_bit(bit) {
-                        let page = idx << 5 | bit;
+                        let page = idx * USIZE_BITS + bit;
                         self.bitmap.set_bit(page, true);
-                        let addr = page << 22;
+                        let addr = BASE + (page << 12);
                         let page = Page {
                             addr: Virtual::new(addr),
                         };
@@ -92,8 +143,51 @@ impl Allocator {
         None
     }
 
+    // allocates count contiguous pages inside window, they're given back with
+    // deallocate_range
+    pub fn allocate_range(
+        &mut self,
+        count: usize,
+        window: Range<Virtual>,
+    ) -> Option<Range<Virtual>> {
+        let first = (window.start.into_inner().max(BASE) - BASE) / PAGE_SIZE;
+        let last = (window.end.into_inner().max(BASE) - BASE) / PAGE_SIZE;
+
+        let mut start = first;
+        for page in first..last.min(PAGES) {
+            if self.bitmap.get_bit(page) {
+                start = page + 1;
+            } else if page + 1 - start == count {
+                for page in start..page + 1 {
+                    self.bitmap.set_bit(page, true);
+                }
+                let start = Virtual::new(BASE + (start << 12));
+                return Some(start..start + count * PAGE_SIZE);
+            }
+        }
+
+        None
+    }
+
+    pub fn deallocate_range(&mut self, range: Range<Virtual>) {
+        let end = range.end.into_inner() - 1;
+        for page in pages(range.start.into_inner()..end) {
+            self.free_raw(page);
+        }
+    }
+
     pub fn deallocate(&mut self, page: Page) {
-        let idx = page.addr.into_inner() >> 22;
+        let addr = page.leak();
+        self.free_raw(addr);
+    }
+
+    fn free_raw(&mut self, addr: Virtual) {
+        let idx = (addr.into_inner() - BASE) >> 12;
+        debug_assert!(
+            self.bitmap.get_bit(idx),
+            "double free of page {:x}",
+            addr,
+        );
         self.bitmap.set_bit(idx, false);
     }
 
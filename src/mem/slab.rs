@@ -0,0 +1,154 @@
+use core::ptr;
+
+use alloc::allocator::{Alloc, AllocErr, Layout};
+
+use spin::Mutex;
+
+use ALLOCATOR;
+use mem::page::PAGE_SIZE;
+
+const CLASSES: usize = 9;
+const SLAB_SIZE: usize = PAGE_SIZE;
+
+// a free object, the first word of every free object links to the next one
+struct Object {
+    next: *mut Object,
+}
+
+struct Cache {
+    size: usize,
+    free: *mut Object,
+    slabs: usize,
+    allocated: usize,
+    free_count: usize,
+    allocations: usize,
+}
+
+// the raw pointers are only touched behind the cache's mutex
+unsafe impl Send for Cache {}
+
+impl Cache {
+    const fn new(size: usize) -> Cache {
+        Cache {
+            size,
+            free: ptr::null_mut(),
+            slabs: 0,
+            allocated: 0,
+            free_count: 0,
+            allocations: 0,
+        }
+    }
+
+    // carves a new slab from the heap into free objects
+    unsafe fn grow(&mut self) -> Result<(), AllocErr> {
+        let layout = Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE);
+        let slab = (&ALLOCATOR).alloc(layout)?;
+
+        for idx in (0..SLAB_SIZE / self.size).rev() {
+            self.push(slab.offset((idx * self.size) as isize));
+        }
+        self.slabs += 1;
+        Ok(())
+    }
+
+    unsafe fn push(&mut self, ptr: *mut u8) {
+        let object = ptr as *mut Object;
+        ptr::write(object, Object { next: self.free });
+        self.free = object;
+        self.free_count += 1;
+    }
+
+    unsafe fn allocate(&mut self) -> Result<*mut u8, AllocErr> {
+        if self.free.is_null() {
+            self.grow()?;
+        }
+
+        let object = self.free;
+        self.free = (*object).next;
+        self.free_count -= 1;
+        self.allocated += 1;
+        self.allocations += 1;
+        Ok(object as *mut u8)
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8) {
+        self.push(ptr);
+        self.allocated -= 1;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            size: self.size,
+            slabs: self.slabs,
+            allocated: self.allocated,
+            free: self.free_count,
+            allocations: self.allocations,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CacheStats {
+    pub size: usize,
+    pub slabs: usize,
+    pub allocated: usize,
+    pub free: usize,
+    // every allocation ever served by this cache
+    pub allocations: usize,
+}
+
+// serves small allocations from per-size caches of page-sized slabs, anything
+// bigger than half a page goes straight to the heap
+//
+// slabs are never returned to the heap
+pub struct SlabAllocator {
+    caches: [Mutex<Cache>; CLASSES],
+}
+
+impl SlabAllocator {
+    pub const fn new() -> SlabAllocator {
+        SlabAllocator {
+            caches: [
+                Mutex::new(Cache::new(8)),
+                Mutex::new(Cache::new(16)),
+                Mutex::new(Cache::new(32)),
+                Mutex::new(Cache::new(64)),
+                Mutex::new(Cache::new(128)),
+                Mutex::new(Cache::new(256)),
+                Mutex::new(Cache::new(512)),
+                Mutex::new(Cache::new(1024)),
+                Mutex::new(Cache::new(2048)),
+            ],
+        }
+    }
+
+    // objects are aligned to their size, so the alignment only has to fit
+    fn class(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        (0..CLASSES).find(|&idx| size <= 8 << idx)
+    }
+
+    pub fn stats(&self) -> [CacheStats; CLASSES] {
+        let mut stats = [CacheStats::default(); CLASSES];
+        for (stats, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stats = cache.lock().stats();
+        }
+        stats
+    }
+}
+
+unsafe impl<'a> Alloc for &'a SlabAllocator {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        match SlabAllocator::class(&layout) {
+            Some(idx) => self.caches[idx].lock().allocate(),
+            None => (&ALLOCATOR).alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match SlabAllocator::class(&layout) {
+            Some(idx) => self.caches[idx].lock().deallocate(ptr),
+            None => (&ALLOCATOR).dealloc(ptr, layout),
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use core::mem;
+use core::ops::Range;
+use core::ptr;
+
+use arch::kernel::{self, KERNEL_BASE};
+use arch::interrupt::exceptions::PageFaultError;
+use arch::paging::addr::*;
+use arch::paging::table::{self, Entry, EntryBuilder, Flags, PageSize, LEVELS,
+                          ROOT_ENTRIES, ROOT_FRAMES};
+use arch::paging::tlb::{self, Batch, Flush};
+use mem::frame::{Frame, LeakCheck, FRAME_SIZE};
+use mem::kmap::{self, KMap};
+use mem::lazy::{self, Region};
+use mem::page::{pages, PAGE_SIZE};
+
+// entries in a single frame of a table, only pae's root spans several frames
+const FRAME_ENTRIES: usize = FRAME_SIZE / mem::size_of::<Entry>();
+
+fn kmap_frame(phys: Physical) -> KMap {
+    kmap::kmap(phys).unwrap_or_else(|| panic!("out of kmap slots"))
+}
+
+// the root entries from this one on map the kernel
+fn kernel_start() -> usize {
+    table::index(Virtual::new(KERNEL_BASE), LEVELS)
+}
+
+fn is_user(idx: usize) -> bool {
+    idx < kernel_start() && !table::is_recursive(idx)
+}
+
+fn is_kernel(idx: usize) -> bool {
+    idx >= kernel_start() && !table::is_recursive(idx)
+}
+
+// the frame of a root and the index in it of root entry idx
+fn root_slot(root: Physical, idx: usize) -> (Physical, usize) {
+    (root + idx / FRAME_ENTRIES * FRAME_SIZE, idx % FRAME_ENTRIES)
+}
+
+// pae's root is five frames big, blocks come in powers of two
+fn root_order() -> usize {
+    (0..).find(|&order| 1 << order >= ROOT_FRAMES).unwrap()
+}
+
+// copies the kernel's root entries, the tables below them are shared
+fn share_kernel(from: Physical, to: Physical) {
+    let first = kernel_start() / FRAME_ENTRIES;
+    for frame in first..(ROOT_ENTRIES / FRAME_ENTRIES) {
+        let mut src = kmap_frame(from + frame * FRAME_SIZE);
+        let mut dst = kmap_frame(to + frame * FRAME_SIZE);
+        let src = src.entries();
+        let dst = dst.entries();
+        for idx in 0..FRAME_ENTRIES {
+            if is_kernel(frame * FRAME_ENTRIES + idx) {
+                dst[idx] = src[idx];
+            }
+        }
+    }
+}
+
+fn new_table() -> Physical {
+    // leaked until the address space is dropped
+    let phys = unsafe { kernel::frame_alloc() }
+        .allocate()
+        .unwrap_or_else(|| panic!("out of frames for page tables"))
+        .leak();
+    for entry in kmap_frame(phys).entries().iter_mut() {
+        *entry = Entry::empty();
+    }
+    phys
+}
+
+// frees a table of level and the tables below it, the frames they map are
+// only released if they're owned
+fn free_table(phys: Physical, level: usize) {
+    let mut slot = kmap_frame(phys);
+    for entry in slot.entries().iter().filter(|entry| entry.is_used()) {
+        if level > 1 && !entry.is_huge() {
+            free_table(entry.into_physical(), level - 1);
+        } else if level == 1 {
+            release(*entry);
+        }
+    }
+    drop(slot);
+    drop(unsafe { Frame::from_raw(phys, 0) });
+}
+
+// drops the space's share of the frame an entry maps, if it owns one
+fn release(entry: Entry) {
+    if entry.flags().contains(Flags::OWNED) {
+        unsafe { kernel::frame_alloc() }.release(entry.into_physical());
+    }
+}
+
+// copies a table of level and the tables below it, owned frames get another
+// owner and writable ones become copy-on-write in both tables
+fn fork_table(phys: Physical, level: usize) -> Physical {
+    let copy = new_table();
+    let mut src = kmap_frame(phys);
+    let mut dst = kmap_frame(copy);
+    let src = src.entries();
+    let dst = dst.entries();
+    for idx in 0..FRAME_ENTRIES {
+        let entry = src[idx];
+        if !entry.is_used() {
+            continue;
+        }
+
+        dst[idx] = if level > 1 && !entry.is_huge() {
+            let table = fork_table(entry.into_physical(), level - 1);
+            with_addr(entry, table)
+        } else if level == 1 && entry.flags().contains(Flags::OWNED) {
+            let mut flags = entry.flags();
+            if flags.contains(Flags::RW) {
+                flags.remove(Flags::RW);
+                flags.insert(Flags::COPY_ON_WRITE);
+            }
+            unsafe { kernel::frame_alloc() }.share(entry.into_physical());
+            src[idx] = with_flags(entry, flags);
+            src[idx]
+        } else {
+            entry
+        };
+    }
+    copy
+}
+
+fn with_addr(entry: Entry, phys: Physical) -> Entry {
+    EntryBuilder::new().addr(phys).flags(entry.flags()).build()
+}
+
+fn with_flags(entry: Entry, flags: Flags) -> Entry {
+    EntryBuilder::new()
+        .addr(entry.into_physical())
+        .flags(flags)
+        .build()
+}
+
+// writes entry idx of the table at frame, returns the previous one if it was
+// present
+fn replace(frame: Physical, idx: usize, entry: Entry) -> Option<Entry> {
+    let mut slot = kmap_frame(frame);
+    let old = slot.entries()[idx];
+    slot.entries()[idx] = entry;
+    if old.is_used() {
+        Some(old)
+    } else {
+        None
+    }
+}
+
+// only RW, USER, NO_EXECUTE and the kernel's own bits of flags are honoured
+fn entry(phys: Physical, flags: Flags) -> Entry {
+    let honoured = Flags::RW | Flags::USER | Flags::OWNED
+        | Flags::COPY_ON_WRITE;
+    let mut builder = EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .flags(flags & honoured)
+        .page_size(PageSize::Normal);
+    if flags.contains(Flags::NO_EXECUTE) {
+        builder = builder.no_execute();
+    }
+    builder.build()
+}
+
+// loads another root, the kernel's tables are carried over from the active
+// one
+//
+// kernel tables created while a root was active only show up in that root
+unsafe fn switch_to(root: Physical) {
+    let active = table::active_root();
+    if active != root {
+        share_kernel(active, root);
+        table::load_root(root);
+    }
+}
+
+fn check_region(range: &Range<Virtual>) {
+    assert!(
+        range.start & (PAGE_SIZE - 1) == 0 && range.end & (PAGE_SIZE - 1) == 0,
+        "region {:08x}-{:08x} must be page-aligned",
+        range.start,
+        range.end,
+    );
+    assert!(
+        range.start < range.end,
+        "region {:08x}-{:08x} is empty",
+        range.start,
+        range.end,
+    );
+    let first = table::index(range.start, LEVELS);
+    let last = table::index(Virtual::new(range.end.into_inner() - 1), LEVELS);
+    assert!(
+        range.end <= KERNEL_BASE && (first..last + 1).all(is_user),
+        "region {:08x}-{:08x} isn't in the user half",
+        range.start,
+        range.end,
+    );
+}
+
+// a hierarchy of page tables whose lower half is its own, everything from
+// KERNEL_BASE on is shared with every other address space
+//
+// its tables are only ever reached through kmap, so it doesn't matter whether
+// it's active
+pub struct AddressSpace {
+    root: Frame,
+}
+
+impl AddressSpace {
+    // an address space with nothing but the kernel mapped
+    pub fn new() -> AddressSpace {
+        AddressSpace::with_kernel_of(table::active_root())
+    }
+
+    fn with_kernel_of(from: Physical) -> AddressSpace {
+        // cr3 can only point below 4 GiB with pae
+        let root = unsafe { kernel::frame_alloc() }
+            .allocate_low(root_order())
+            .unwrap_or_else(|| panic!("out of frames for an address space"));
+        table::init_root(*root.addr());
+        share_kernel(from, *root.addr());
+        AddressSpace { root }
+    }
+
+    // a new address space that shares the kernel with this one, but none of
+    // its user mappings
+    pub fn clone_kernel_only(&self) -> AddressSpace {
+        AddressSpace::with_kernel_of(self.root())
+    }
+
+    pub fn root(&self) -> Physical {
+        *self.root.addr()
+    }
+
+    pub fn is_active(&self) -> bool {
+        table::active_root() == self.root()
+    }
+
+    pub fn activate(&mut self) {
+        unsafe { switch_to(self.root()) }
+    }
+
+    // the page table and index in it of the entry for virt, missing tables
+    // are created if create is set
+    fn walk(
+        &mut self,
+        virt: Virtual,
+        create: bool,
+    ) -> Option<(Physical, usize)> {
+        let (mut frame, mut idx) =
+            root_slot(self.root(), table::index(virt, LEVELS));
+        for level in (1..LEVELS).rev() {
+            let mut slot = kmap_frame(frame);
+            let entry = slot.entries()[idx];
+            frame = if entry.is_used() {
+                assert!(
+                    !entry.is_huge(),
+                    "{:08x} is already mapped by a huge page",
+                    virt,
+                );
+                entry.into_physical()
+            } else if create {
+                // the leaf entries decide what's actually allowed
+                let table = new_table();
+                slot.entries()[idx] = EntryBuilder::new()
+                    .addr(table)
+                    .present()
+                    .read_write()
+                    .user()
+                    .page_size(PageSize::Normal)
+                    .build();
+                table
+            } else {
+                return None;
+            };
+            idx = table::index(virt, level);
+        }
+        Some((frame, idx))
+    }
+
+    // replaces the entry for virt, a frame the space owned is released
+    fn set(&mut self, virt: Virtual, entry: Entry, active: bool) {
+        let (table, idx) = self.walk(virt, true).unwrap();
+        let flush = Flush::new(virt, replace(table, idx, entry));
+        let old = if active { flush.flush() } else { flush.ignore() };
+        if let Some(old) = old {
+            release(old);
+        }
+    }
+
+    // maps range to the frames from phys on, which stay owned by the caller,
+    // whatever was mapped there before is replaced
+    //
+    // only RW, USER and NO_EXECUTE of flags are honoured
+    pub fn map_region(
+        &mut self,
+        range: Range<Virtual>,
+        phys: Physical,
+        flags: Flags,
+    ) {
+        check_region(&range);
+
+        let active = self.is_active();
+        let flags = flags - Flags::OWNED - Flags::COPY_ON_WRITE;
+        let end = range.end.into_inner() - 1;
+        for (idx, virt) in pages(range.start.into_inner()..end).enumerate() {
+            self.set(virt, entry(phys + idx * PAGE_SIZE, flags), active);
+        }
+    }
+
+    // maps range to zeroed frames that belong to the space
+    //
+    // only RW, USER and NO_EXECUTE of flags are honoured
+    pub fn allocate_region(&mut self, range: Range<Virtual>, flags: Flags) {
+        check_region(&range);
+
+        let active = self.is_active();
+        let flags = (flags - Flags::COPY_ON_WRITE) | Flags::OWNED;
+        let end = range.end.into_inner() - 1;
+        for virt in pages(range.start.into_inner()..end) {
+            // released when it's unmapped or the space is dropped
+            let phys = unsafe { kernel::frame_alloc() }
+                .allocate()
+                .unwrap_or_else(|| panic!("out of frames for {:08x}", virt))
+                .leak();
+            unsafe {
+                let slot = kmap_frame(phys);
+                ptr::write_bytes(slot.as_ptr::<u8>(), 0, PAGE_SIZE);
+            }
+            self.set(virt, entry(phys, flags), active);
+        }
+    }
+
+    // unmaps range, frames the space owns are released, the others stay with
+    // the caller, page tables stay until the space is dropped
+    pub fn unmap_region(&mut self, range: Range<Virtual>) {
+        check_region(&range);
+
+        let active = self.is_active();
+        let mut batch = Batch::new();
+        let end = range.end.into_inner() - 1;
+        for virt in pages(range.start.into_inner()..end) {
+            if let Some((table, idx)) = self.walk(virt, false) {
+                let flush =
+                    Flush::new(virt, replace(table, idx, Entry::empty()));
+                let old = if active {
+                    batch.add(flush)
+                } else {
+                    flush.ignore()
+                };
+                if let Some(old) = old {
+                    release(old);
+                }
+            }
+        }
+        batch.flush();
+    }
+
+    // makes range a lazy region of the space, its frames are only allocated
+    // and mapped when a page is first touched while the space is active, they
+    // belong to the space like those of allocate_region
+    //
+    // only RW, USER and NO_EXECUTE of flags are honoured
+    pub fn register_lazy(&mut self, range: Range<Virtual>, flags: Flags) {
+        check_region(&range);
+        lazy::insert(Some(self.root()), range, flags);
+    }
+
+    // forgets the lazy region that starts at start, the frames it got so far
+    // are released
+    pub fn unregister_lazy(&mut self, start: Virtual) -> Option<Region> {
+        let region = lazy::remove(Some(self.root()), start);
+        if let Some(ref region) = region {
+            self.unmap_region(region.range().clone());
+        }
+        region
+    }
+
+    // a copy of the space that shares its frames, owned writable ones are
+    // copied by the page-fault handler once either side writes to them
+    pub fn fork(&mut self) -> AddressSpace {
+        let child = self.clone_kernel_only();
+
+        let last = kernel_start() / FRAME_ENTRIES;
+        for frame in 0..(last + 1).min(ROOT_ENTRIES / FRAME_ENTRIES) {
+            let mut src = kmap_frame(self.root() + frame * FRAME_SIZE);
+            let mut dst = kmap_frame(child.root() + frame * FRAME_SIZE);
+            let src = src.entries();
+            let dst = dst.entries();
+            for idx in 0..FRAME_ENTRIES {
+                let entry = src[idx];
+                if !is_user(frame * FRAME_ENTRIES + idx) || !entry.is_used() {
+                    continue;
+                }
+
+                dst[idx] = if entry.is_huge() {
+                    entry
+                } else {
+                    let table = fork_table(entry.into_physical(), LEVELS - 1);
+                    with_addr(entry, table)
+                };
+            }
+        }
+
+        lazy::fork(self.root(), child.root());
+
+        // the parent's writable pages just became read-only
+        if self.is_active() {
+            unsafe { tlb::flush_all() }
+        }
+        child
+    }
+}
+
+// called by the page-fault handler for writes to present pages, returns
+// whether the fault was resolved
+//
+// a frame that's still shared is copied, the last owner gets to write to it
+pub fn resolve(addr: Virtual, error: PageFaultError) -> bool {
+    if !error.contains(PageFaultError::PRESENT | PageFaultError::WRITE) {
+        return false;
+    }
+
+    let page = addr & !(PAGE_SIZE - 1);
+    let (phys, flags) =
+        match kernel::try_page_table().and_then(|table| table.translate(page)) {
+            Some(mapping) => mapping,
+            None => return false,
+        };
+    if !flags.contains(Flags::COPY_ON_WRITE) {
+        return false;
+    }
+    if error.contains(PageFaultError::USER) && !flags.contains(Flags::USER) {
+        return false;
+    }
+
+    let shares = match kernel::try_frame_alloc() {
+        Some(frames) => frames.shares(phys),
+        None => return false,
+    };
+    let frame = if shares > 0 {
+        let frame = match kernel::try_frame_alloc()
+            .and_then(|mut frames| frames.allocate())
+        {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let copy = match kmap::try_kmap(*frame.addr()) {
+            Some(copy) => copy,
+            None => return false,
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(
+                page.into_inner() as *const u8,
+                copy.as_ptr::<u8>(),
+                PAGE_SIZE,
+            );
+        }
+        drop(copy);
+
+        // the new frame is owned by this space alone
+        match kernel::try_frame_alloc() {
+            Some(mut frames) => {
+                frames.release(phys);
+            }
+            None => return false,
+        }
+        frame.leak()
+    } else {
+        phys
+    };
+
+    let flags = (flags - Flags::COPY_ON_WRITE) | Flags::RW;
+    match kernel::try_page_table() {
+        Some(mut table) => {
+            table
+                .remap_page(page, entry(frame, flags))
+                .unwrap()
+                .flush();
+            true
+        }
+        None => false,
+    }
+}
+
+// forks an address space and writes to the same pages on both sides, one
+// allocated and one lazy, each side has to see its own writes only, and every
+// frame has to be freed again
+pub fn test_fork() -> bool {
+    // in debug builds, also no frame handle may be left behind
+    let _leaks = LeakCheck::new();
+    let page = Virtual::new(0x400000);
+    let lazy = page + PAGE_SIZE;
+    let previous = table::active_root();
+    let free = unsafe { kernel::frame_alloc() }.free();
+
+    // what both pages hold, if they agree
+    let read = || unsafe {
+        let first = ptr::read_volatile(page.into_inner() as *const u32);
+        let second = ptr::read_volatile(lazy.into_inner() as *const u32);
+        if first == second {
+            Some(first)
+        } else {
+            None
+        }
+    };
+    let write = |value| unsafe {
+        ptr::write_volatile(page.into_inner() as *mut u32, value);
+        ptr::write_volatile(lazy.into_inner() as *mut u32, value);
+    };
+
+    let (child_first, parent_value, child_value) = unsafe {
+        let mut parent = AddressSpace::new();
+        parent.allocate_region(page..page + PAGE_SIZE, Flags::RW);
+        parent.register_lazy(lazy..lazy + PAGE_SIZE, Flags::RW);
+        parent.activate();
+        write(1);
+
+        let mut child = parent.fork();
+        // the parent gets a copy
+        write(2);
+
+        child.activate();
+        let child_first = read();
+        // the child is the last owner, it keeps the frame
+        write(3);
+
+        parent.activate();
+        let parent_value = read();
+        child.activate();
+        let child_value = read();
+
+        switch_to(previous);
+        (child_first, parent_value, child_value)
+    };
+
+    let leaked = unsafe { kernel::frame_alloc() }.free() != free;
+    child_first == Some(1) && parent_value == Some(2) && child_value == Some(3)
+        && !leaked
+}
+
+impl Drop for AddressSpace {
+    // the root's frames go with self.root
+    fn drop(&mut self) {
+        assert!(
+            !self.is_active(),
+            "the active address space can't be dropped",
+        );
+        lazy::forget(self.root());
+
+        let last = kernel_start() / FRAME_ENTRIES;
+        for frame in 0..(last + 1).min(ROOT_ENTRIES / FRAME_ENTRIES) {
+            let mut slot = kmap_frame(self.root() + frame * FRAME_SIZE);
+            for (idx, entry) in slot.entries().iter().enumerate() {
+                if is_user(frame * FRAME_ENTRIES + idx) && entry.is_used()
+                    && !entry.is_huge()
+                {
+                    free_table(entry.into_physical(), LEVELS - 1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+use spin::{Mutex, Once};
+
+use arch::kernel::{self, KMAP_BASE, STACKS_BASE};
+use arch::paging::addr::*;
+use arch::paging::table::{EntryBuilder, PageSize};
+use mem::frame::Frame;
+use mem::page::{pages, PAGE_SIZE};
+
+pub const STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+static GUARDS: Once<Mutex<Vec<Guard>>> = Once::new();
+
+// an unmapped page right below a stack, running into it means the stack
+// overflowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Guard {
+    page: Virtual,
+    owner: &'static str,
+}
+
+fn guards() -> &'static Mutex<Vec<Guard>> {
+    GUARDS.call_once(|| Mutex::new(Vec::new()))
+}
+
+// makes faults at page report an overflow of owner's stack, the page must not
+// be mapped
+pub fn register_guard(page: Virtual, owner: &'static str) {
+    guards().lock().push(Guard { page, owner });
+}
+
+fn unregister_guard(page: Virtual) {
+    guards().lock().retain(|guard| guard.page != page);
+}
+
+// the owner of the stack whose guard page addr lies in, for the fault
+// handlers
+pub fn overflowed(addr: Virtual) -> Option<&'static str> {
+    let page = addr & !(PAGE_SIZE - 1);
+    GUARDS
+        .try()
+        .and_then(|guards| guards.try_lock())
+        .and_then(|guards| {
+            guards
+                .iter()
+                .find(|guard| guard.page == page)
+                .map(|guard| guard.owner)
+        })
+}
+
+// a kernel stack of STACK_SIZE bytes above an unmapped guard page, its pages
+// and frames are freed when it's dropped
+//
+// stacks live in the kernel half, so they're there in every address space
+#[derive(Debug)]
+pub struct Stack {
+    // the guard page included
+    range: Range<Virtual>,
+    owner: &'static str,
+}
+
+impl Stack {
+    // None if there are no pages or frames left
+    pub fn new(owner: &'static str) -> Option<Stack> {
+        let count = STACK_SIZE / PAGE_SIZE + 1;
+        let window = Virtual::new(STACKS_BASE)..Virtual::new(KMAP_BASE);
+        let range =
+            unsafe { kernel::page_alloc() }.allocate_range(count, window)?;
+        let guard = range.start;
+
+        let mapped = {
+            let mut table = unsafe { kernel::page_table() };
+            let mut frames = unsafe { kernel::frame_alloc() };
+
+            let end = range.end.into_inner() - 1;
+            let mut mapped = 0;
+            for page in pages(guard.into_inner() + PAGE_SIZE..end) {
+                let frame = match frames.allocate() {
+                    // taken back when the stack is dropped
+                    Some(frame) => frame.leak(),
+                    None => break,
+                };
+                let entry = EntryBuilder::new()
+                    .addr(frame)
+                    .present()
+                    .read_write()
+                    .global()
+                    .no_execute()
+                    .page_size(PageSize::Normal)
+                    .build();
+                table.map_page(page, entry, &mut frames).flush();
+                mapped += 1;
+            }
+            mapped
+        };
+
+        let stack = Stack { range, owner };
+        if mapped < count - 1 {
+            // gives back whatever has been mapped
+            return None;
+        }
+
+        register_guard(guard, owner);
+        Some(stack)
+    }
+
+    pub fn owner(&self) -> &'static str {
+        self.owner
+    }
+
+    pub fn guard(&self) -> Virtual {
+        self.range.start
+    }
+
+    pub fn bottom(&self) -> Virtual {
+        self.range.start + PAGE_SIZE
+    }
+
+    // the initial stack pointer, stacks grow down
+    pub fn top(&self) -> Virtual {
+        self.range.end
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        unregister_guard(self.guard());
+
+        let end = self.range.end.into_inner() - 1;
+        for page in pages(self.bottom().into_inner()..end) {
+            let old = unsafe { kernel::page_table() }.unmap(page).flush();
+            if let Some(old) = old {
+                let _frame = unsafe { Frame::from_raw(old.into_physical(), 0) };
+            }
+        }
+
+        unsafe { kernel::page_alloc() }.deallocate_range(self.range.clone());
+    }
+}
@@ -1,6 +1,7 @@
 use core::fmt::{self, Write};
 
 use drivers::vga;
+use power;
 
 #[lang = "panic_fmt"]
 #[no_mangle]
@@ -16,6 +17,10 @@ pub extern "C" fn panic_fmt(
         let _ = write!(vga, "', {}:{}:{}\x1b[0m", file, line, col);
     });
 
+    if cfg!(feature = "reboot-on-panic") {
+        power::reboot();
+    }
+
     loop {}
 }
 
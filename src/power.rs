@@ -0,0 +1,138 @@
+use spin::Once;
+
+use x86::shared::irq;
+
+use acpi::{dsdt, sdt, Fadt, Sdt};
+use arch::Kinfo;
+use port::Port;
+
+// the 8042 controller can pulse the cpu's reset line
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 0b10;
+const KBC_RESET: u8 = 0xfe;
+
+// PM1 control register bits
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+
+// emulators power off when these values are written to these ports
+const EMULATOR_SHUTDOWN: [(u16, u16); 3] = [
+    (0x604, 0x2000),  // qemu
+    (0xb004, 0x2000), // bochs and older qemu
+    (0x4004, 0x3400), // virtualbox
+];
+
+// how often a status bit is polled before giving up on it
+const POLL_TRIES: usize = 1000000;
+
+static FADT: Once<Fadt> = Once::new();
+// SLP_TYPa and SLP_TYPb for soft off
+static S5: Once<(u8, u8)> = Once::new();
+
+// keeps what reboot and shutdown need from the acpi tables, they're mapped
+// and parsed here because the panic handler can't
+pub fn init(kinfo: &Kinfo) {
+    let fadt = match kinfo.acpi.as_ref().and_then(|acpi| acpi.fadt) {
+        Some(fadt) => *FADT.call_once(|| fadt),
+        None => return,
+    };
+
+    let s5 = Sdt::map(fadt.dsdt)
+        .and_then(|dsdt| dsdt::sleep_type(&dsdt, b"_S5_"));
+    if let Some(s5) = s5 {
+        S5.call_once(|| s5);
+    }
+}
+
+// tries the acpi reset register, the 8042 and a triple fault, in that order
+pub fn reboot() -> ! {
+    unsafe { irq::disable() };
+
+    if let Some(fadt) = FADT.try() {
+        acpi_reset(fadt);
+    }
+
+    let mut status = unsafe { Port::new(KBC_STATUS) };
+    for _ in 0..POLL_TRIES {
+        if status.read_byte() & KBC_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    status.write_byte(KBC_RESET);
+
+    triple_fault()
+}
+
+// only i/o space reset registers, mapping memory would take locks the panic
+// handler may interrupt
+fn acpi_reset(fadt: &Fadt) {
+    if !fadt.can_reset() {
+        return;
+    }
+    let (reg, value) = fadt.reset.unwrap();
+    if reg.space == sdt::SPACE_IO {
+        unsafe { Port::new(reg.address as u16) }.write_byte(value);
+    }
+}
+
+// with an empty idt the breakpoint can't be delivered, which escalates to a
+// triple fault and the cpu resets
+fn triple_fault() -> ! {
+    // large enough for either idtr layout
+    static EMPTY: [u8; 16] = [0; 16];
+    unsafe {
+        asm!("lidt    ($0)
+              int3" : : "r"(&EMPTY) : "memory" : "volatile");
+    }
+    loop {}
+}
+
+// tries acpi soft off through the PM1 control ports, then the emulators'
+// shutdown ports, and halts if nothing worked
+pub fn shutdown() -> ! {
+    unsafe { irq::disable() };
+
+    if let (Some(fadt), Some(&(a, b))) = (FADT.try(), S5.try()) {
+        acpi_shutdown(fadt, a, b);
+    }
+
+    for &(port, value) in EMULATOR_SHUTDOWN.iter() {
+        unsafe { Port::new(port) }.write_word(value);
+    }
+
+    loop {
+        unsafe { asm!("hlt" : : : : "volatile") };
+    }
+}
+
+fn acpi_shutdown(fadt: &Fadt, a: u8, b: u8) {
+    if fadt.pm1a_control == 0 {
+        return;
+    }
+    let mut pm1a = unsafe { Port::new(fadt.pm1a_control) };
+
+    // the firmware may still be in legacy mode, where acpi is handed over by
+    // writing to the smi command port
+    if pm1a.read_word() & SCI_EN == 0 && fadt.smi_command != 0 {
+        unsafe { Port::new(fadt.smi_command) }.write_byte(fadt.acpi_enable);
+        for _ in 0..POLL_TRIES {
+            if pm1a.read_word() & SCI_EN != 0 {
+                break;
+            }
+        }
+    }
+
+    sleep(&mut pm1a, a);
+    if fadt.pm1b_control != 0 {
+        sleep(&mut unsafe { Port::new(fadt.pm1b_control) }, b);
+    }
+}
+
+// enters the sleep state of type typ, the rest of the register, SCI_EN among
+// it, has to keep its value
+fn sleep(pm1: &mut Port, typ: u8) {
+    let value = pm1.read_word() & !(SLP_TYP | SLP_EN);
+    pm1.write_word(value | (typ as u16) << SLP_TYP_SHIFT & SLP_TYP | SLP_EN);
+}
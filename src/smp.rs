@@ -0,0 +1,267 @@
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use alloc::boxed::Box;
+
+use x86::shared::control_regs::{cr3, cr4};
+use x86::shared::irq;
+
+use arch::{kernel, smp, Kinfo};
+use arch::paging::addr::*;
+use arch::paging::table::{EntryBuilder, PageSize};
+use drivers::apic::{self, LocalApic};
+use drivers::pit::delay;
+use mem::kmap;
+use mem::page::{self, Page};
+use mem::stack::Stack;
+use macros::*;
+
+// how long the startup sequence waits, in microseconds
+const INIT_DELAY: usize = 10000;
+const STARTUP_DELAY: usize = 200;
+const ONLINE_TIMEOUT: usize = 1000000;
+// the wait between two looks at the online count
+const ONLINE_POLL: usize = 100;
+
+// cpus that are done starting up, the bootstrap processor included
+static ONLINE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// what the trampoline reads from trampoline_args, every field is as wide as
+// a register
+#[repr(C)]
+struct Args {
+    cr3: usize,
+    cr4: usize,
+    efer: usize,
+    stack: usize,
+    entry: usize,
+    arg: usize,
+}
+
+// the data every cpu has for itself, fs or gs point at it
+#[repr(C)]
+#[derive(Debug)]
+pub struct PerCpu {
+    // has to come first, smp::percpu reads it through fs or gs
+    this: *const PerCpu,
+    pub id: usize,
+    pub apic_id: u8,
+    // None for the bootstrap processor, which keeps its boot stack
+    stack: Option<Stack>,
+}
+
+impl PerCpu {
+    // the block lives as long as its cpu, i.e. forever
+    fn leak(
+        id: usize,
+        apic_id: u8,
+        stack: Option<Stack>,
+    ) -> &'static PerCpu {
+        let percpu = Box::into_raw(Box::new(PerCpu {
+            this: ptr::null(),
+            id,
+            apic_id,
+            stack,
+        }));
+        unsafe {
+            (*percpu).this = percpu;
+            &*percpu
+        }
+    }
+
+    unsafe fn load(&'static self) {
+        smp::set_percpu(self as *const _ as usize, mem::size_of::<PerCpu>());
+    }
+}
+
+// the block of the cpu this runs on, None until the cpu loaded it
+pub fn current() -> Option<&'static PerCpu> {
+    if !smp::has_percpu() {
+        return None;
+    }
+    Some(unsafe { &*(smp::percpu() as *const PerCpu) })
+}
+
+// how many cpus run the kernel
+pub fn count() -> usize {
+    ONLINE.load(Ordering::SeqCst)
+}
+
+fn announce() {
+    let percpu = current().expect("per-cpu block used before smp::init");
+    kprintln!("cpu {} online, apic id {}", percpu.id, percpu.apic_id);
+}
+
+// gives the bootstrap processor its per-cpu block and starts every other
+// enabled cpu in the madt, one after the other, returns how many cpus run
+//
+// needs the local apic, without it or a madt only the bootstrap processor
+// runs
+pub fn init(kinfo: &Kinfo) -> usize {
+    let local = apic::local();
+    let bsp_id = local.map_or(0, |local| local.id());
+
+    unsafe { PerCpu::leak(0, bsp_id, None).load() };
+    ONLINE.store(1, Ordering::SeqCst);
+    announce();
+
+    let madt = kinfo.acpi.as_ref().and_then(|acpi| acpi.madt.as_ref());
+    let (local, madt) = match (local, madt) {
+        (Some(local), Some(madt)) => (local, madt),
+        _ => return count(),
+    };
+
+    if !install() {
+        return count();
+    }
+
+    let aps = madt.cpus
+        .iter()
+        .filter(|cpu| cpu.enabled && cpu.apic_id != bsp_id);
+    for (id, cpu) in aps.enumerate() {
+        let stack = match Stack::new("application processor") {
+            Some(stack) => stack,
+            None => break,
+        };
+        let top = stack.top().into_inner();
+        let percpu = PerCpu::leak(id + 1, cpu.apic_id, Some(stack));
+
+        // a cpu that comes up late would find the next cpu's arguments, so
+        // nothing else is started after one didn't
+        if !start(local, percpu, top) {
+            kprintln!("cpu with apic id {} didn't start", cpu.apic_id);
+            break;
+        }
+    }
+
+    uninstall();
+    count()
+}
+
+// init, startup and startup again if the first one didn't do it, as intel
+// suggests, true once the cpu is online
+fn start(local: &LocalApic, percpu: &'static PerCpu, stack: usize) -> bool {
+    let args = Args {
+        cr3: unsafe { cr3() },
+        cr4: unsafe { cr4().bits() },
+        efer: smp::efer(),
+        stack,
+        entry: ap_main as usize,
+        arg: percpu as *const _ as usize,
+    };
+    match kmap::kmap(Physical::new(smp::TRAMPOLINE as PhysAddr)) {
+        Some(slot) => unsafe {
+            let offset = smp::args_offset() as isize;
+            let addr = slot.as_ptr::<u8>().offset(offset) as *mut Args;
+            ptr::write_volatile(addr, args);
+        },
+        None => return false,
+    }
+
+    let online = count();
+    let page = (smp::TRAMPOLINE >> 12) as u8;
+
+    local.send_init(percpu.apic_id);
+    delay(INIT_DELAY);
+    local.send_startup(percpu.apic_id, page);
+    delay(STARTUP_DELAY);
+    if count() == online {
+        local.send_startup(percpu.apic_id, page);
+    }
+
+    for _ in 0..ONLINE_TIMEOUT / ONLINE_POLL {
+        if count() > online {
+            return true;
+        }
+        delay(ONLINE_POLL);
+    }
+    false
+}
+
+// copies the trampoline to smp::TRAMPOLINE and identity maps it there, the
+// cpus keep running from it after they enabled paging
+//
+// false if something else already uses the page at smp::TRAMPOLINE
+fn install() -> bool {
+    let virt = Virtual::new(smp::TRAMPOLINE);
+    // the page allocator only covers the kernel half on x86_64
+    let page = if page::managed(virt) {
+        let mut pages = unsafe { kernel::page_alloc() };
+        match pages.allocate_at(virt) {
+            Some(page) => if *page.addr() == smp::TRAMPOLINE {
+                Some(page)
+            } else {
+                pages.deallocate(page);
+                return false;
+            },
+            None => return false,
+        }
+    } else {
+        None
+    };
+
+    let phys = Physical::new(smp::TRAMPOLINE as PhysAddr);
+    let code = smp::trampoline();
+    match kmap::kmap(phys) {
+        Some(slot) => unsafe {
+            let dst = slot.as_ptr::<u8>();
+            ptr::copy_nonoverlapping(code.as_ptr(), dst, code.len());
+        },
+        None => {
+            if let Some(page) = page {
+                unsafe { kernel::page_alloc() }.deallocate(page);
+            }
+            return false;
+        }
+    }
+
+    let entry = EntryBuilder::new()
+        .addr(phys)
+        .present()
+        .page_size(PageSize::Normal)
+        .build();
+    let mut table = unsafe { kernel::page_table() };
+    let mut frames = unsafe { kernel::frame_alloc() };
+    table.map_page(virt, entry, &mut frames).flush();
+    // uninstall gives the page back
+    if let Some(page) = page {
+        page.leak();
+    }
+    true
+}
+
+fn uninstall() {
+    let virt = Virtual::new(smp::TRAMPOLINE);
+    unsafe { kernel::page_table() }.unmap(virt).flush();
+    if page::managed(virt) {
+        let page = unsafe { Page::from_raw(virt) };
+        unsafe { kernel::page_alloc() }.deallocate(page);
+    }
+}
+
+// where the trampoline leaves an application processor, on its own stack
+// with the bootstrap processor's page tables and the trampoline's gdt
+//
+// the cpu announces itself and halts with interrupts disabled, nothing is
+// scheduled on it yet
+extern "C" fn ap_main(percpu: &'static PerCpu) -> ! {
+    unsafe {
+        kernel::init_ap_gdt();
+        kernel::init_idt();
+        percpu.load();
+    }
+    if let Some(local) = apic::local() {
+        local.enable(apic::SPURIOUS_VECTOR);
+    }
+
+    announce();
+    ONLINE.fetch_add(1, Ordering::SeqCst);
+
+    loop {
+        unsafe {
+            irq::disable();
+            asm!("hlt" : : : : "volatile");
+        }
+    }
+}